@@ -0,0 +1,487 @@
+//! Derive macros for the serial traits and the metadata of packets.
+//!
+//! The generated code refers to the `haendler_protocol` crate by its
+//! absolute path, so it must be a dependency of every crate using these macros.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Fields, Ident, LitInt, Token};
+
+/// Path of the protocol crate
+fn krate() -> TokenStream2 {
+  quote!(::haendler_protocol)
+}
+
+/// Path of the module containing the packets and the serial traits
+fn root() -> TokenStream2 {
+  let krate = krate();
+  quote!(#krate::packet)
+}
+
+// Attribute arguments
+
+/// Argument of an attribute, either `key` or `key = value`
+struct Arg {
+  key: Ident,
+  value: Option<ArgValue>,
+}
+
+enum ArgValue {
+  Ident(Ident),
+  Int(LitInt),
+}
+
+struct Args(Punctuated<Arg, Token![,]>);
+
+impl Parse for Arg {
+  fn parse(input: ParseStream) -> syn::Result<Self> {
+    let key = input.call(syn::ext::IdentExt::parse_any)?;
+    let value = if input.peek(Token![=]) {
+      input.parse::<Token![=]>()?;
+      if input.peek(LitInt) {
+        Some(ArgValue::Int(input.parse()?))
+      } else {
+        Some(ArgValue::Ident(input.parse()?))
+      }
+    } else {
+      None
+    };
+    Ok(Self { key, value })
+  }
+}
+
+impl Parse for Args {
+  fn parse(input: ParseStream) -> syn::Result<Self> {
+    Ok(Self(Punctuated::parse_terminated(input)?))
+  }
+}
+
+impl Arg {
+  fn ident(&self) -> syn::Result<&Ident> {
+    match &self.value {
+      Some(ArgValue::Ident(ident)) => Ok(ident),
+      _ => Err(syn::Error::new(
+        self.key.span(),
+        format!("expected `{} = <name>`", self.key),
+      )),
+    }
+  }
+  fn int(&self) -> syn::Result<&LitInt> {
+    match &self.value {
+      Some(ArgValue::Int(int)) => Ok(int),
+      _ => Err(syn::Error::new(
+        self.key.span(),
+        format!("expected `{} = <integer>`", self.key),
+      )),
+    }
+  }
+}
+
+/// Collects the arguments of all `#[serial(..)]` attributes
+fn serial_args(attrs: &[Attribute]) -> syn::Result<Vec<Arg>> {
+  let mut args = Vec::new();
+  for attr in attrs.iter().filter(|a| a.path.is_ident("serial")) {
+    args.extend(attr.parse_args::<Args>()?.0);
+  }
+  Ok(args)
+}
+
+// Fields
+
+/// Wire format of a field
+enum Encoding {
+  /// Uses the `SerialRead`/`SerialWrite` implementation of the type
+  Plain,
+  /// `String` with a length prefix of the given type
+  String(Ident),
+  /// `Vec` with a length prefix of the given type
+  List(Ident),
+}
+
+impl Encoding {
+  fn of(attrs: &[Attribute]) -> syn::Result<Self> {
+    let mut encoding = Encoding::Plain;
+    for arg in serial_args(attrs)? {
+      encoding = match arg.key.to_string().as_str() {
+        "name" => Encoding::String(format_ident!("u8")),
+        "str" => Encoding::String(format_ident!("u32")),
+        "list" => match arg.value {
+          None => Encoding::List(format_ident!("u32")),
+          Some(_) => Encoding::List(arg.ident()?.clone()),
+        },
+        _ => {
+          return Err(syn::Error::new(
+            arg.key.span(),
+            "expected `name`, `str`, `list` or `list = <prefix>`",
+          ))
+        }
+      };
+    }
+    Ok(encoding)
+  }
+  fn read(&self) -> TokenStream2 {
+    let root = root();
+    match self {
+      Encoding::Plain => quote!(#root::serial::SerialRead::read(data)),
+      Encoding::String(prefix) => quote!(#root::serial::read_string::<#prefix>(data)),
+      Encoding::List(prefix) => quote!(#root::serial::read_list::<#prefix, _>(data)),
+    }
+  }
+  fn write(&self, value: &Ident) -> TokenStream2 {
+    let root = root();
+    match self {
+      Encoding::Plain => quote!(#root::serial::SerialWrite::write_consume(#value, buf);),
+      Encoding::String(prefix) => quote!(#root::serial::write_string::<#prefix>(#value, buf);),
+      Encoding::List(prefix) => quote!(#root::serial::write_list::<#prefix, _>(#value, buf);),
+    }
+  }
+}
+
+/// Field of a struct together with the variable it is bound to
+struct Field {
+  /// Name used in decode errors
+  name: String,
+  binding: Ident,
+  encoding: Encoding,
+}
+
+fn fields(fields: &Fields) -> syn::Result<Vec<Field>> {
+  fields
+    .iter()
+    .enumerate()
+    .map(|(i, field)| {
+      let (name, binding) = match &field.ident {
+        Some(ident) => (ident.to_string(), ident.clone()),
+        None => (i.to_string(), format_ident!("field{}", i)),
+      };
+      Ok(Field {
+        name,
+        binding,
+        encoding: Encoding::of(&field.attrs)?,
+      })
+    })
+    .collect()
+}
+
+/// Builds the constructor `Self { a: .., b: .. }` / `Self(.., ..)`
+fn constructor(
+  path: &TokenStream2,
+  shape: &Fields,
+  fields: &[Field],
+  values: &[TokenStream2],
+) -> TokenStream2 {
+  match shape {
+    Fields::Named(_) => {
+      let names = fields.iter().map(|f| &f.binding);
+      quote!(#path { #(#names: #values),* })
+    }
+    Fields::Unnamed(_) => quote!(#path(#(#values),*)),
+    Fields::Unit => quote!(#path),
+  }
+}
+
+/// Builds the pattern binding all fields
+fn pattern(path: &TokenStream2, shape: &Fields, fields: &[Field]) -> TokenStream2 {
+  let bindings = fields.iter().map(|f| &f.binding);
+  match shape {
+    Fields::Named(_) => quote!(#path { #(#bindings),* }),
+    Fields::Unnamed(_) => quote!(#path(#(#bindings),*)),
+    Fields::Unit => quote!(#path),
+  }
+}
+
+/// Reads all fields and builds the value
+fn read_fields(path: &TokenStream2, shape: &Fields) -> syn::Result<TokenStream2> {
+  let root = root();
+  let fields = fields(shape)?;
+  let values: Vec<_> = fields
+    .iter()
+    .map(|field| {
+      let read = field.encoding.read();
+      let name = &field.name;
+      quote!(#root::DecodeContext::field(#read, #name)?)
+    })
+    .collect();
+  Ok(constructor(path, shape, &fields, &values))
+}
+
+// Enums
+
+/// Integer type of the discriminant, `#[serial(repr = u16)]`, defaults to `u8`
+fn enum_repr(attrs: &[Attribute]) -> syn::Result<Ident> {
+  let mut repr = format_ident!("u8");
+  for arg in serial_args(attrs)? {
+    if arg.key == "repr" {
+      repr = arg.ident()?.clone();
+    } else {
+      return Err(syn::Error::new(
+        arg.key.span(),
+        "expected `repr = <integer type>`",
+      ));
+    }
+  }
+  Ok(repr)
+}
+
+/// Discriminants of the variants, following the rules of Rust: the explicit
+/// one or the one of the previous variant plus one, starting at zero
+fn discriminants(data: &syn::DataEnum) -> Vec<TokenStream2> {
+  let mut base = quote!(0);
+  let mut next = 0;
+  data
+    .variants
+    .iter()
+    .map(|variant| {
+      if let Some((_, expr)) = &variant.discriminant {
+        base = quote!(#expr);
+        next = 0;
+      }
+      let discriminant = match next {
+        0 => base.clone(),
+        _ => {
+          let offset = proc_macro2::Literal::usize_unsuffixed(next);
+          quote!((#base) + #offset)
+        }
+      };
+      next += 1;
+      discriminant
+    })
+    .collect()
+}
+
+// Derives
+
+/// Derives `SerialRead` for structs and enums.
+///
+/// Fields are read in order. `#[serial(name)]` and `#[serial(str)]` read a
+/// `String` with a `u8` or `u32` length prefix, `#[serial(list)]` reads a
+/// `Vec` with a `u32` length prefix, or the one given by `#[serial(list = u16)]`.
+/// Enums are read from their discriminant, see `#[serial(repr = ..)]`,
+/// followed by the fields of the variant.
+#[proc_macro_derive(SerialRead, attributes(serial))]
+pub fn derive_serial_read(input: TokenStream) -> TokenStream {
+  let input = parse_macro_input!(input as DeriveInput);
+  expand_serial_read(&input)
+    .unwrap_or_else(|e| e.to_compile_error())
+    .into()
+}
+
+fn expand_serial_read(input: &DeriveInput) -> syn::Result<TokenStream2> {
+  let root = root();
+  let ident = &input.ident;
+  let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+  let body = match &input.data {
+    Data::Struct(data) => {
+      let value = read_fields(&quote!(Self), &data.fields)?;
+      quote!(Ok(#value))
+    }
+    Data::Enum(data) => {
+      let repr = enum_repr(&input.attrs)?;
+      let discriminants = discriminants(data);
+      let values = data
+        .variants
+        .iter()
+        .map(|variant| {
+          let ident = &variant.ident;
+          read_fields(&quote!(Self::#ident), &variant.fields)
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+      quote! {
+        let value: #repr = #root::serial::SerialRead::read(data)?;
+        #(
+          if value == (#discriminants) as #repr {
+            return Ok(#values);
+          }
+        )*
+        // Point at the discriminant that was just read
+        Err(#root::DecodeError::new(
+          #root::DecodeErrorKind::InvalidEnumValue,
+          data.len() + std::mem::size_of::<#repr>(),
+        ))
+      }
+    }
+    Data::Union(_) => {
+      return Err(syn::Error::new(ident.span(), "unions can't be derived"));
+    }
+  };
+
+  Ok(quote! {
+    impl #impl_generics #root::serial::SerialRead for #ident #ty_generics #where_clause {
+      #[allow(unused_variables)]
+      fn read(data: &mut &[u8]) -> Result<Self, #root::DecodeError> {
+        #body
+      }
+    }
+  })
+}
+
+/// Derives `SerialWrite` for structs and enums.
+///
+/// Accepts the same attributes as `SerialRead`. The type must implement
+/// `Clone`.
+#[proc_macro_derive(SerialWrite, attributes(serial))]
+pub fn derive_serial_write(input: TokenStream) -> TokenStream {
+  let input = parse_macro_input!(input as DeriveInput);
+  expand_serial_write(&input)
+    .unwrap_or_else(|e| e.to_compile_error())
+    .into()
+}
+
+fn expand_serial_write(input: &DeriveInput) -> syn::Result<TokenStream2> {
+  let root = root();
+  let ident = &input.ident;
+  let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+  let body = match &input.data {
+    Data::Struct(data) => {
+      let fields = fields(&data.fields)?;
+      let pattern = pattern(&quote!(Self), &data.fields, &fields);
+      let writes = fields.iter().map(|f| f.encoding.write(&f.binding));
+      quote! {
+        let #pattern = self;
+        #(#writes)*
+      }
+    }
+    Data::Enum(data) => {
+      let repr = enum_repr(&input.attrs)?;
+      let discriminants = discriminants(data);
+      let arms = data
+        .variants
+        .iter()
+        .zip(discriminants)
+        .map(|(variant, discriminant)| {
+          let ident = &variant.ident;
+          let fields = fields(&variant.fields)?;
+          let pattern = pattern(&quote!(Self::#ident), &variant.fields, &fields);
+          let writes = fields.iter().map(|f| f.encoding.write(&f.binding));
+          Ok(quote! {
+            #pattern => {
+              #root::serial::SerialWrite::write_consume((#discriminant) as #repr, buf);
+              #(#writes)*
+            }
+          })
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+      quote! {
+        match self {
+          #(#arms)*
+        }
+      }
+    }
+    Data::Union(_) => {
+      return Err(syn::Error::new(ident.span(), "unions can't be derived"));
+    }
+  };
+
+  Ok(quote! {
+    impl #impl_generics #root::serial::SerialWrite for #ident #ty_generics #where_clause {
+      #[allow(unused_variables)]
+      fn write_consume(self, buf: &mut Vec<u8>) {
+        #body
+      }
+    }
+  })
+}
+
+/// Declares a packet and derives its serial traits.
+///
+/// ```ignore
+/// #[packet(id = 3, state = Login, bound = server, permission = Moderator)]
+/// pub struct DeleteGamePacket {
+///   pub id: u64,
+/// }
+/// ```
+///
+/// `bound` is `server` for packets sent by clients (`IngoingPacket`),
+/// `client` for packets sent by the server (`OutgoingPacket`) or `both`.
+/// `permission` is required unless the packet is only client bound. Both
+/// serial traits are derived regardless of the direction, so that clients
+/// can use the same packets.
+/// Packets must implement `Clone`.
+#[proc_macro_attribute]
+pub fn packet(args: TokenStream, item: TokenStream) -> TokenStream {
+  let args = parse_macro_input!(args as Args);
+  let item = parse_macro_input!(item as DeriveInput);
+  expand_packet(args, item)
+    .unwrap_or_else(|e| e.to_compile_error())
+    .into()
+}
+
+fn expand_packet(args: Args, item: DeriveInput) -> syn::Result<TokenStream2> {
+  let krate = krate();
+  let root = root();
+  let ident = &item.ident;
+
+  let mut id = None;
+  let mut state = None;
+  let mut bound = None;
+  let mut permission = None;
+  for arg in &args.0 {
+    match arg.key.to_string().as_str() {
+      "id" => id = Some(arg.int()?),
+      "state" => state = Some(arg.ident()?),
+      "bound" => bound = Some(arg.ident()?),
+      "permission" => permission = Some(arg.ident()?),
+      _ => {
+        return Err(syn::Error::new(
+          arg.key.span(),
+          "expected `id`, `state`, `bound` or `permission`",
+        ))
+      }
+    }
+  }
+  let missing = |name| syn::Error::new(ident.span(), format!("missing `{}` in #[packet]", name));
+  let id = id.ok_or_else(|| missing("id"))?;
+  let state = state.ok_or_else(|| missing("state"))?;
+  let bound = bound.ok_or_else(|| missing("bound"))?;
+
+  let (ingoing, outgoing) = match bound.to_string().as_str() {
+    "server" => (true, false),
+    "client" => (false, true),
+    "both" => (true, true),
+    _ => {
+      return Err(syn::Error::new(
+        bound.span(),
+        "expected `server`, `client` or `both`",
+      ))
+    }
+  };
+  if let (Some(permission), false) = (permission, ingoing) {
+    return Err(syn::Error::new(
+      permission.span(),
+      "only server bound packets require a permission",
+    ));
+  }
+
+  let mut output = quote! {
+    #[derive(#root::serial::SerialRead, #root::serial::SerialWrite)]
+    #item
+  };
+
+  let (impl_generics, ty_generics, where_clause) = item.generics.split_for_impl();
+  if ingoing {
+    let permission = permission.ok_or_else(|| missing("permission"))?;
+    output.extend(quote! {
+      impl #impl_generics #root::IngoingPacket for #ident #ty_generics #where_clause {
+        const ID: u16 = #id;
+        const STATE: #krate::State = #krate::State::#state;
+        const PERMISSION: #krate::PermissionLevel = #krate::PermissionLevel::#permission;
+      }
+    });
+  }
+  if outgoing {
+    output.extend(quote! {
+      impl #impl_generics #root::OutgoingPacket for #ident #ty_generics #where_clause {
+        const ID: u16 = #id;
+        const STATE: #krate::State = #krate::State::#state;
+      }
+    });
+  }
+  Ok(output)
+}
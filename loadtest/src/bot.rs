@@ -0,0 +1,306 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::time;
+
+use haendler_protocol::client::{ClientBoundPacket, ClientError};
+use haendler_protocol::packet::*;
+use haendler_protocol::{HspClient, State};
+use tokio_rustls::rustls::ClientConfig;
+
+use crate::options::Options;
+use crate::stats::{Action, Stats};
+
+// Structures
+
+/// Simulated player with its own connection
+pub struct Bot {
+  id: usize,
+  options: Arc<Options>,
+  tls: Arc<ClientConfig>,
+  client: Option<HspClient>,
+  /// Games announced by the server
+  games: BTreeSet<u64>,
+  rng: StdRng,
+  /// Number of chat messages sent, makes every message unique
+  messages: u64,
+  stats: Stats,
+}
+
+#[derive(Debug)]
+pub enum BotError {
+  Client(ClientError),
+  Timeout,
+  PermissionDenied,
+  /// The server rejected the action
+  Failed(String),
+}
+
+// Implementations
+
+impl Bot {
+  pub fn new(id: usize, options: Arc<Options>, tls: Arc<ClientConfig>) -> Self {
+    Self {
+      id,
+      options,
+      tls,
+      client: None,
+      games: BTreeSet::new(),
+      rng: StdRng::from_entropy(),
+      messages: 0,
+      stats: Stats::default(),
+    }
+  }
+  /// Performs actions at the configured rate until the deadline is reached
+  pub async fn run(mut self, deadline: Instant) -> Stats {
+    let period = self.options.period();
+    // Spread the bots evenly, so they don't all act at once
+    time::delay_for(period.mul_f64(self.rng.gen())).await;
+    let mut interval = time::interval(period);
+
+    while Instant::now() < deadline {
+      self.idle(&mut interval).await;
+      let action = match &self.client {
+        None => Action::Connect,
+        Some(client) => self.feasible(client.state()),
+      };
+
+      let start = Instant::now();
+      let result = match time::timeout(self.options.timeout(), self.perform(action)).await {
+        Ok(result) => result,
+        Err(_) => Err(BotError::Timeout),
+      };
+      match result {
+        Ok(()) => self.stats.record(action, start.elapsed()),
+        Err(e) => {
+          if e.is_fatal() {
+            // The state of the connection is unknown, start over
+            self.client = None;
+          }
+          self.stats.error(action, e.to_string());
+        }
+      }
+    }
+
+    if let Some(client) = self.client.take() {
+      drop(client.shutdown().await);
+    }
+    self.stats
+  }
+  /// Keeps receiving until the next action is due, like a real client
+  async fn idle(&mut self, interval: &mut time::Interval) {
+    loop {
+      let client = match &mut self.client {
+        Some(client) => client,
+        None => {
+          interval.tick().await;
+          return;
+        }
+      };
+      tokio::select! {
+        _ = interval.tick() => return,
+        result = client.receive() => match result {
+          Ok(packet) => self.observe(&packet),
+          Err(e) => {
+            self.client = None;
+            self.stats.error(Action::Idle, BotError::from(e).to_string());
+          }
+        },
+      }
+    }
+  }
+  /// Picks an action from the mix. Lobby actions make bots in a game
+  /// leave it and game actions make bots in the lobby join a game.
+  fn feasible(&mut self, state: State) -> Action {
+    let action = match (state, self.options.mix.choose(&mut self.rng)) {
+      (State::Game, Action::Login) | (State::Game, Action::List) | (State::Game, Action::Join) => {
+        Action::Leave
+      }
+      (State::Login, Action::Chat)
+      | (State::Login, Action::Info)
+      | (State::Login, Action::Leave) => Action::Join,
+      (_, action) => action,
+    };
+    // Bots that don't know any game look for one first
+    if action == Action::Join && self.games.is_empty() {
+      Action::List
+    } else {
+      action
+    }
+  }
+  async fn perform(&mut self, action: Action) -> Result<(), BotError> {
+    match action {
+      Action::Idle => unreachable!("idling is not part of the mix"),
+      Action::Connect => {
+        let client = self.connect(HandshakeAction::Connect).await?;
+        self.client = Some(client);
+        // The server sends the game list when entering the lobby
+        self
+          .response(|p| matches!(p, ClientBoundPacket::ListGames(_)))
+          .await?;
+        Ok(())
+      }
+      Action::Ping => {
+        let mut client = self.connect(HandshakeAction::Ping).await?;
+        client.expect::<PingStatusPacket>().await?;
+        let random = self.rng.gen();
+        client.send(PingPongPacket { random }).await?;
+        let pong: PingPongPacket = client.expect().await?;
+        if pong.random != random {
+          return Err(BotError::Failed("ping: wrong pong".into()));
+        }
+        Ok(())
+      }
+      Action::Login => {
+        let packet = LoginPacket {
+          username: self.options.username.clone(),
+          password: self.options.password.clone(),
+        };
+        self.client()?.send(packet).await?;
+        self
+          .response(|p| matches!(p, ClientBoundPacket::LoginResponse(_)))
+          .await?;
+        Ok(())
+      }
+      Action::List => {
+        self.client()?.send(SyncGamesPacket {}).await?;
+        self
+          .response(|p| matches!(p, ClientBoundPacket::ListGames(_)))
+          .await?;
+        Ok(())
+      }
+      Action::Join => {
+        let count = self.games.len();
+        if count == 0 {
+          return Err(BotError::Failed("join: no games".into()));
+        }
+        let id = *self.games.iter().nth(self.rng.gen_range(0, count)).unwrap();
+        self.client()?.send(JoinGamePacket { id }).await?;
+        let response = self
+          .response(|p| matches!(p, ClientBoundPacket::JoinGameResponse(_)))
+          .await?;
+        match response {
+          ClientBoundPacket::JoinGameResponse(response)
+            if response.status != GameActionStatus::Success =>
+          {
+            self.games.remove(&id);
+            Err(BotError::Failed(format!("join: {:?}", response.status)))
+          }
+          _ => Ok(()),
+        }
+      }
+      Action::Chat => {
+        self.messages += 1;
+        let message = format!("Bot {} says hello #{}", self.id, self.messages);
+        let packet = ChatPacket {
+          message: message.clone(),
+        };
+        self.client()?.send(packet).await?;
+        // Wait for the own message, others may be received before it
+        self
+          .response(|p| match p {
+            ClientBoundPacket::ChatMessage(chat) => chat.message == message,
+            _ => false,
+          })
+          .await?;
+        Ok(())
+      }
+      Action::Info => {
+        self.client()?.send(RequestGameInfoPacket {}).await?;
+        self
+          .response(|p| matches!(p, ClientBoundPacket::GameInfo(_)))
+          .await?;
+        Ok(())
+      }
+      Action::Leave => {
+        self.client()?.send(LeaveGamePacket {}).await?;
+        self
+          .response(|p| matches!(p, ClientBoundPacket::LeftGame(_)))
+          .await?;
+        Ok(())
+      }
+    }
+  }
+  async fn connect(&self, action: HandshakeAction) -> Result<HspClient, BotError> {
+    let client = HspClient::connect(
+      self.options.address.as_str(),
+      &self.options.domain,
+      self.tls.clone(),
+      action,
+      Capabilities::default(),
+    )
+    .await?;
+    Ok(client)
+  }
+  fn client(&mut self) -> Result<&mut HspClient, BotError> {
+    self
+      .client
+      .as_mut()
+      .ok_or_else(|| BotError::Failed("not connected".into()))
+  }
+  /// Receives packets until one matches. Game lists are tracked meanwhile.
+  async fn response<F>(&mut self, matches: F) -> Result<ClientBoundPacket, BotError>
+  where
+    F: Fn(&ClientBoundPacket) -> bool,
+  {
+    loop {
+      let packet = self.client()?.receive().await?;
+      if let ClientBoundPacket::PermissionDenied(_) = packet {
+        return Err(BotError::PermissionDenied);
+      }
+      self.observe(&packet);
+      if matches(&packet) {
+        return Ok(packet);
+      }
+    }
+  }
+  /// Tracks the games announced by the server
+  fn observe(&mut self, packet: &ClientBoundPacket) {
+    if let ClientBoundPacket::ListGames(list) = packet {
+      for entry in &list.entries {
+        match entry {
+          ListGamesEntry::Add { id, .. } => self.games.insert(*id),
+          ListGamesEntry::Remove { id } => self.games.remove(id),
+        };
+      }
+    }
+  }
+}
+
+impl BotError {
+  /// Whether the connection can't be used anymore
+  fn is_fatal(&self) -> bool {
+    match self {
+      Self::Client(ClientError::UnexpectedPacket(_)) => false,
+      Self::Client(ClientError::WrongState { .. }) => false,
+      Self::Client(_) | Self::Timeout => true,
+      Self::PermissionDenied | Self::Failed(_) => false,
+    }
+  }
+}
+
+impl From<ClientError> for BotError {
+  fn from(e: ClientError) -> Self {
+    Self::Client(e)
+  }
+}
+
+impl fmt::Display for BotError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Client(ClientError::Io(e)) => write!(f, "io: {:?}", e.kind()),
+      Self::Client(ClientError::Disconnected(packet)) => {
+        write!(f, "disconnected: {:?}", packet.reason)
+      }
+      Self::Client(ClientError::UnexpectedPacket(_)) => f.write_str("unexpected packet"),
+      Self::Client(e) => write!(f, "{}", e),
+      Self::Timeout => f.write_str("timeout"),
+      Self::PermissionDenied => f.write_str("permission denied"),
+      Self::Failed(message) => f.write_str(message),
+    }
+  }
+}
@@ -0,0 +1,125 @@
+//! Load test of a Haendlerspiel server. Opens many connections that perform
+//! a mix of actions at a given rate and reports latencies and errors.
+
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Instant;
+
+use structopt::StructOpt;
+
+use haendler_protocol::client::ClientError;
+use haendler_protocol::packet::*;
+use haendler_protocol::{HspClient, PermissionLevel};
+use tokio_rustls::rustls::ClientConfig;
+
+mod bot;
+mod options;
+mod stats;
+
+use bot::Bot;
+use options::Options;
+use stats::Stats;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+#[tokio::main]
+async fn main() -> Result<(), BoxError> {
+  let options = Options::from_args();
+  options.validate()?;
+  let tls = options.tls_config()?;
+  let options = Arc::new(options);
+
+  let games = create_games(&options, &tls).await?;
+
+  println!(
+    "(ℹ) Running {} connections at {} actions/s each for {} s",
+    options.connections,
+    options.rate,
+    options.duration().as_secs()
+  );
+  let start = Instant::now();
+  let deadline = start + options.duration();
+  let bots: Vec<_> = (0..options.connections)
+    .map(|id| tokio::spawn(Bot::new(id, options.clone(), tls.clone()).run(deadline)))
+    .collect();
+
+  let mut stats = Stats::default();
+  for bot in bots {
+    stats.merge(bot.await?);
+  }
+  let elapsed = start.elapsed();
+
+  delete_games(&options, &tls, games).await?;
+  println!();
+  stats.report(elapsed);
+  Ok(())
+}
+
+/// Logs in with the account of the bots
+async fn moderator(options: &Options, tls: &Arc<ClientConfig>) -> Result<HspClient, BoxError> {
+  let mut client = HspClient::connect(
+    options.address.as_str(),
+    &options.domain,
+    tls.clone(),
+    HandshakeAction::Connect,
+    Capabilities::default(),
+  )
+  .await?;
+  client
+    .send(LoginPacket {
+      username: options.username.clone(),
+      password: options.password.clone(),
+    })
+    .await?;
+  let response: LoginResponsePacket = wait_for(&mut client).await?;
+  if response.permission_level < PermissionLevel::Moderator {
+    return Err("creating games requires a moderator account".into());
+  }
+  Ok(client)
+}
+
+/// Creates the games the bots play in
+async fn create_games(options: &Options, tls: &Arc<ClientConfig>) -> Result<Vec<u64>, BoxError> {
+  let mut games = Vec::with_capacity(options.create_games);
+  if options.create_games == 0 {
+    return Ok(games);
+  }
+  let mut client = moderator(options, tls).await?;
+  for i in 0..options.create_games {
+    let name = format!("Load test {}", i + 1);
+    client.send(CreateGamePacket { name }).await?;
+    let response: CreateGameResponsePacket = wait_for(&mut client).await?;
+    games.push(response.id);
+  }
+  println!("(ℹ) Created {} games", games.len());
+  client.shutdown().await?;
+  Ok(games)
+}
+
+async fn delete_games(
+  options: &Options,
+  tls: &Arc<ClientConfig>,
+  games: Vec<u64>,
+) -> Result<(), BoxError> {
+  if games.is_empty() {
+    return Ok(());
+  }
+  let mut client = moderator(options, tls).await?;
+  for id in games {
+    client.send(DeleteGamePacket { id }).await?;
+    wait_for::<DeleteGameResponsePacket>(&mut client).await?;
+  }
+  println!("(ℹ) Deleted the created games");
+  client.shutdown().await?;
+  Ok(())
+}
+
+/// Waits for a packet of type `P`, skipping all other packets
+async fn wait_for<P: OutgoingPacket + 'static>(client: &mut HspClient) -> Result<P, ClientError> {
+  loop {
+    match client.expect().await {
+      Err(ClientError::UnexpectedPacket(_)) => continue,
+      result => return result,
+    }
+  }
+}
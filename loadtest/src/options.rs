@@ -0,0 +1,152 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rand::Rng;
+use structopt::StructOpt;
+
+use haendler_protocol::client;
+use tokio_rustls::rustls::internal::pemfile::certs;
+use tokio_rustls::rustls::ClientConfig;
+
+use crate::stats::Action;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "haendler-loadtest")]
+pub struct Options {
+  /// Address of the server
+  #[structopt(short = "a", long = "address", default_value = "127.0.0.1:25252")]
+  pub address: String,
+
+  /// Name the certificate of the server is checked against
+  #[structopt(long = "domain", default_value = "localhost")]
+  pub domain: String,
+
+  /// Certificate of the server, e.g. a self-signed one. The server must
+  /// present exactly this certificate.
+  #[structopt(short = "C", long = "tls-cert", parse(from_os_str))]
+  cert: Option<PathBuf>,
+
+  /// Accept any certificate instead of checking it
+  #[structopt(long = "insecure")]
+  insecure: bool,
+
+  /// Number of concurrent connections
+  #[structopt(short = "n", long = "connections", default_value = "10")]
+  pub connections: usize,
+
+  /// Actions per second of every connection
+  #[structopt(short = "r", long = "rate", default_value = "1")]
+  pub rate: f64,
+
+  /// Duration of the test in seconds
+  #[structopt(short = "d", long = "duration", default_value = "30")]
+  duration: u64,
+
+  /// Time in milliseconds to wait for a response
+  #[structopt(long = "timeout", default_value = "5000")]
+  timeout: u64,
+
+  /// Relative weights of the actions, e.g. `ping=1,list=4,chat=8`
+  #[structopt(
+    long = "mix",
+    default_value = "ping=1,login=1,list=4,join=2,chat=8,info=4,leave=1"
+  )]
+  pub mix: Mix,
+
+  /// Account the bots log in with (logs off if empty)
+  #[structopt(short = "u", long = "username", default_value = "")]
+  pub username: String,
+
+  #[structopt(short = "p", long = "password", default_value = "")]
+  pub password: String,
+
+  /// Number of games created before the test and deleted afterwards.
+  /// Requires an account with the moderator permission level.
+  #[structopt(long = "create-games", default_value = "0")]
+  pub create_games: usize,
+}
+
+/// Weighted set of actions
+#[derive(Clone, Debug)]
+pub struct Mix {
+  weights: Vec<(Action, u32)>,
+  total: u32,
+}
+
+impl Options {
+  pub fn duration(&self) -> Duration {
+    Duration::from_secs(self.duration)
+  }
+  pub fn timeout(&self) -> Duration {
+    Duration::from_millis(self.timeout)
+  }
+  /// Time between two actions of a connection
+  pub fn period(&self) -> Duration {
+    Duration::from_secs_f64(1.0 / self.rate)
+  }
+  pub fn tls_config(&self) -> io::Result<Arc<ClientConfig>> {
+    match (&self.cert, self.insecure) {
+      (Some(path), _) => {
+        let certs = certs(&mut BufReader::new(File::open(path)?))
+          .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid cert"))?;
+        Ok(client::pinned(certs))
+      }
+      (None, true) => Ok(client::insecure()),
+      (None, false) => Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "either --tls-cert or --insecure is required",
+      )),
+    }
+  }
+  pub fn validate(&self) -> Result<(), String> {
+    if self.connections == 0 {
+      return Err("at least one connection is required".into());
+    }
+    if !(self.rate > 0.0 && self.rate.is_finite()) {
+      return Err("the rate must be positive".into());
+    }
+    Ok(())
+  }
+}
+
+impl Mix {
+  pub fn choose<R: Rng>(&self, rng: &mut R) -> Action {
+    let mut n = rng.gen_range(0, self.total);
+    for &(action, weight) in &self.weights {
+      if n < weight {
+        return action;
+      }
+      n -= weight;
+    }
+    unreachable!("weights add up to the total")
+  }
+}
+
+impl FromStr for Mix {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let mut weights = Vec::new();
+    for part in s.split(',') {
+      let mut split = part.splitn(2, '=');
+      let action: Action = split.next().unwrap_or_default().trim().parse()?;
+      let weight = match split.next() {
+        Some(weight) => weight
+          .trim()
+          .parse()
+          .map_err(|_| format!("invalid weight {:?}", weight))?,
+        None => 1,
+      };
+      weights.push((action, weight));
+    }
+    let total = weights.iter().map(|(_, weight)| weight).sum();
+    if total == 0 {
+      return Err("the weights must not all be zero".into());
+    }
+    Ok(Self { weights, total })
+  }
+}
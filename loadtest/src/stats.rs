@@ -0,0 +1,154 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+// Structures
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Action {
+  /// Connects and performs the handshake, done whenever a bot has no connection
+  Connect,
+  /// Pings the server using a separate connection
+  Ping,
+  Login,
+  /// Synchronizes the game list
+  List,
+  Join,
+  Chat,
+  /// Requests the game info
+  Info,
+  Leave,
+  /// Receives packets between the actions
+  Idle,
+}
+
+/// Latencies and errors collected by the bots
+#[derive(Debug, Default)]
+pub struct Stats {
+  latencies: HashMap<Action, Vec<Duration>>,
+  errors: HashMap<Action, u64>,
+  /// Number of errors per kind
+  error_kinds: BTreeMap<String, u64>,
+}
+
+// Implementations
+
+impl Stats {
+  pub fn record(&mut self, action: Action, latency: Duration) {
+    self.latencies.entry(action).or_default().push(latency);
+  }
+  pub fn error(&mut self, action: Action, kind: String) {
+    *self.errors.entry(action).or_default() += 1;
+    *self.error_kinds.entry(kind).or_default() += 1;
+  }
+  pub fn merge(&mut self, other: Stats) {
+    for (action, latencies) in other.latencies {
+      self.latencies.entry(action).or_default().extend(latencies);
+    }
+    for (action, count) in other.errors {
+      *self.errors.entry(action).or_default() += count;
+    }
+    for (kind, count) in other.error_kinds {
+      *self.error_kinds.entry(kind).or_default() += count;
+    }
+  }
+  /// Prints latency percentiles, throughput and errors
+  pub fn report(mut self, elapsed: Duration) {
+    let mut actions: Vec<Action> = self
+      .latencies
+      .keys()
+      .chain(self.errors.keys())
+      .copied()
+      .collect();
+    actions.sort();
+    actions.dedup();
+
+    println!(
+      "{:<8} {:>8} {:>8} {:>9} {:>9} {:>9} {:>9}",
+      "Action", "Ok", "Errors", "p50 ms", "p90 ms", "p99 ms", "max ms"
+    );
+    let mut total = 0;
+    for action in actions {
+      let latencies = self.latencies.entry(action).or_default();
+      latencies.sort();
+      total += latencies.len();
+      println!(
+        "{:<8} {:>8} {:>8} {:>9} {:>9} {:>9} {:>9}",
+        action.to_string(),
+        latencies.len(),
+        self.errors.get(&action).copied().unwrap_or(0),
+        millis(percentile(latencies, 50.0)),
+        millis(percentile(latencies, 90.0)),
+        millis(percentile(latencies, 99.0)),
+        millis(latencies.last().copied()),
+      );
+    }
+
+    println!(
+      "\n{} actions in {:.1} s ({:.1} actions/s)",
+      total,
+      elapsed.as_secs_f64(),
+      total as f64 / elapsed.as_secs_f64()
+    );
+    if !self.error_kinds.is_empty() {
+      println!("\nErrors:");
+      for (kind, count) in &self.error_kinds {
+        println!("{:>8}  {}", count, kind);
+      }
+    }
+  }
+}
+
+/// Nearest-rank percentile of sorted latencies
+fn percentile(sorted: &[Duration], p: f64) -> Option<Duration> {
+  if sorted.is_empty() {
+    return None;
+  }
+  let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+  Some(sorted[rank.max(1) - 1])
+}
+
+fn millis(latency: Option<Duration>) -> String {
+  match latency {
+    Some(latency) => format!("{:.1}", latency.as_secs_f64() * 1000.0),
+    None => "-".into(),
+  }
+}
+
+impl fmt::Display for Action {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Self::Connect => "connect",
+      Self::Ping => "ping",
+      Self::Login => "login",
+      Self::List => "list",
+      Self::Join => "join",
+      Self::Chat => "chat",
+      Self::Info => "info",
+      Self::Leave => "leave",
+      Self::Idle => "idle",
+    })
+  }
+}
+
+impl FromStr for Action {
+  type Err = String;
+
+  /// Parses an action of the mix. Connecting is not part of the mix.
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "ping" => Ok(Self::Ping),
+      "login" => Ok(Self::Login),
+      "list" => Ok(Self::List),
+      "join" => Ok(Self::Join),
+      "chat" => Ok(Self::Chat),
+      "info" => Ok(Self::Info),
+      "leave" => Ok(Self::Leave),
+      _ => Err(format!(
+        "unknown action {:?}, expected ping, login, list, join, chat, info or leave",
+        s
+      )),
+    }
+  }
+}
@@ -0,0 +1,435 @@
+//! Async client of the protocol. It performs the handshake, keeps track of
+//! the connection state and offers typed access to all packets.
+
+use std::any::Any;
+use std::convert::TryInto;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::{self, Certificate, ClientConfig};
+use tokio_rustls::TlsConnector;
+
+use crate::frame::{self, Header, HEADER_LEN};
+use crate::packet::serial::SerialRead;
+use crate::packet::*;
+use crate::State;
+
+// Structures
+
+/// Connection to a server. All packets sent and received are checked
+/// against the current state.
+pub struct HspClient {
+  stream: TlsStream<TcpStream>,
+  state: State,
+  capabilities: Capabilities,
+  max_body_size: u32,
+  /// Received bytes of incomplete packets
+  buffer: Vec<u8>,
+  /// Round-trip time reported by the last heartbeat
+  rtt: Option<Duration>,
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+  Io(io::Error),
+  /// A packet sent by the server could not be decoded
+  Decode(DecodeError),
+  /// The server closed the connection with a Disconnect packet
+  Disconnected(DisconnectPacket),
+  /// A different packet than the expected one was received
+  UnexpectedPacket(ClientBoundPacket),
+  /// The packet can't be sent in the current state
+  WrongState {
+    expected: State,
+    actual: State,
+  },
+}
+
+/// Default limit of the body size of received packets (16 MiB)
+pub const DEFAULT_MAX_BODY_SIZE: u32 = 16 * 1024 * 1024;
+
+macro_rules! client_bound {
+  ($($V:ident = $P:ident),* $(,)?) => {
+    /// Any packet sent by the server, except for Disconnect and Heartbeat
+    #[derive(Clone, Debug)]
+    pub enum ClientBoundPacket {
+      $($V($P)),*
+    }
+
+    impl ClientBoundPacket {
+      fn decode(id: u16, state: State, mut data: &[u8]) -> Result<Self, DecodeError> {
+        $(
+          let (packet_id, packet_state) = (<$P as OutgoingPacket>::ID, <$P as OutgoingPacket>::STATE);
+          if id == packet_id && (packet_state == state || packet_state == State::Any) {
+            let packet = $P::read(&mut data)?;
+            if !data.is_empty() {
+              return Err(DecodeError::new(DecodeErrorKind::TrailingBytes, data.len()));
+            }
+            return Ok(Self::$V(packet));
+          }
+        )*
+        Err(DecodeError::new(DecodeErrorKind::UnknownPacket, data.len()))
+      }
+      fn into_any(self) -> Box<dyn Any> {
+        match self {
+          $(Self::$V(packet) => Box::new(packet)),*
+        }
+      }
+    }
+  };
+}
+
+client_bound! {
+  // --- State = Handshake ---
+  HandshakeResponse = HandshakeResponsePacket,
+  // --- State = Ping ---
+  PingStatus = PingStatusPacket,
+  PingPong = PingPongPacket,
+  // --- State = Login ---
+  ListGames = ListGamesPacket,
+  LoginResponse = LoginResponsePacket,
+  CreateGameResponse = CreateGameResponsePacket,
+  DeleteGameResponse = DeleteGameResponsePacket,
+  RenameGameResponse = RenameGameResponsePacket,
+  JoinGameResponse = JoinGameResponsePacket,
+  // --- State = Game ---
+  LeftGame = LeftGamePacket,
+  GameInfo = GameInfoPacket,
+  ChatMessage = ChatMessagePacket,
+  // --- State = Any ---
+  PermissionDenied = PermissionDeniedPacket,
+  DecodeError = DecodeErrorPacket,
+}
+
+// Implementations
+
+impl HspClient {
+  /// Connects to a server and performs the handshake. `domain` is the name
+  /// the certificate of the server is checked against.
+  pub async fn connect<A: ToSocketAddrs>(
+    address: A,
+    domain: &str,
+    config: Arc<ClientConfig>,
+    action: HandshakeAction,
+    capabilities: Capabilities,
+  ) -> Result<Self, ClientError> {
+    let domain = webpki::DNSNameRef::try_from_ascii_str(domain)
+      .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid domain name"))?;
+    let tcp = TcpStream::connect(address).await?;
+    // Packets are small and sent one by one
+    tcp.set_nodelay(true)?;
+    let stream = TlsConnector::from(config).connect(domain, tcp).await?;
+
+    let mut client = Self {
+      stream,
+      state: State::Handshake,
+      capabilities: Capabilities::default(),
+      max_body_size: DEFAULT_MAX_BODY_SIZE,
+      buffer: Vec::new(),
+      rtt: None,
+    };
+    client
+      .send(HandshakePacket {
+        protocol_version: PROTOCOL_VERSION,
+        action,
+        capabilities,
+      })
+      .await?;
+    let response: HandshakeResponsePacket = client.expect().await?;
+    client.capabilities = response.capabilities;
+    client.state = match action {
+      HandshakeAction::Ping => State::Ping,
+      HandshakeAction::Connect => State::Login,
+    };
+    Ok(client)
+  }
+  pub fn state(&self) -> State {
+    self.state
+  }
+  /// Capabilities negotiated during the handshake
+  pub fn capabilities(&self) -> Capabilities {
+    self.capabilities
+  }
+  /// Round-trip time measured by the server
+  pub fn rtt(&self) -> Option<Duration> {
+    self.rtt
+  }
+  pub fn set_max_body_size(&mut self, max_body_size: u32) {
+    self.max_body_size = max_body_size;
+  }
+  pub async fn send<P: IngoingPacket>(&mut self, packet: P) -> Result<(), ClientError> {
+    if P::STATE != self.state && P::STATE != State::Any {
+      return Err(ClientError::WrongState {
+        expected: P::STATE,
+        actual: self.state,
+      });
+    }
+    let data = frame::encode(P::ID, packet);
+    self.stream.write_all(&data).await?;
+    self.stream.flush().await?;
+    Ok(())
+  }
+  /// Receives the next packet. A Disconnect packet is returned as an error,
+  /// heartbeats are answered automatically. The server closes connections
+  /// that don't answer heartbeats, so clients must keep receiving. No data
+  /// is lost if receiving is cancelled, e.g. by `tokio::select!`.
+  pub async fn receive(&mut self) -> Result<ClientBoundPacket, ClientError> {
+    loop {
+      if let Some(packet) = self.receive_frame().await? {
+        return Ok(packet);
+      }
+    }
+  }
+  /// Receives a single packet, returns `None` if it was a heartbeat
+  async fn receive_frame(&mut self) -> Result<Option<ClientBoundPacket>, ClientError> {
+    let (header, body) = self.read_frame().await?;
+
+    let in_packet = |e: DecodeError| e.in_packet(header.packet_id, self.state, body.len());
+    let mut data = &body[..];
+    match header.packet_id {
+      DisconnectPacket::ID => {
+        let packet = DisconnectPacket::read(&mut data).map_err(in_packet)?;
+        return Err(ClientError::Disconnected(packet));
+      }
+      <HeartbeatPacket as OutgoingPacket>::ID => {
+        let packet = HeartbeatPacket::read(&mut data).map_err(in_packet)?;
+        if packet.rtt != 0 {
+          self.rtt = Some(Duration::from_micros(packet.rtt.into()));
+        }
+        self.send(packet).await?;
+        return Ok(None);
+      }
+      _ => (),
+    }
+    let packet =
+      ClientBoundPacket::decode(header.packet_id, self.state, &body).map_err(in_packet)?;
+
+    // Follow the state changes caused by the server
+    match &packet {
+      ClientBoundPacket::JoinGameResponse(response)
+        if response.status == GameActionStatus::Success =>
+      {
+        self.state = State::Game;
+      }
+      ClientBoundPacket::LeftGame(_) => self.state = State::Login,
+      _ => (),
+    }
+    Ok(Some(packet))
+  }
+  /// Reads the header and body of the next packet
+  async fn read_frame(&mut self) -> Result<(Header, Vec<u8>), ClientError> {
+    loop {
+      if self.buffer.len() >= HEADER_LEN {
+        let header = Header::parse(self.buffer[..HEADER_LEN].try_into().unwrap());
+        if header.body_len > self.max_body_size {
+          return Err(
+            io::Error::new(
+              io::ErrorKind::InvalidData,
+              format!("packet {:#X} is too large", header.packet_id),
+            )
+            .into(),
+          );
+        }
+        let frame_len = HEADER_LEN + header.body_len as usize;
+        if self.buffer.len() >= frame_len {
+          let body = self.buffer[HEADER_LEN..frame_len].to_vec();
+          self.buffer.drain(..frame_len);
+          return Ok((header, body));
+        }
+      }
+      // A single read either completes or reads nothing, unlike `read_exact`
+      let mut chunk = [0; 4096];
+      let bytes = self.stream.read(&mut chunk).await?;
+      if bytes == 0 {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+      }
+      self.buffer.extend_from_slice(&chunk[..bytes]);
+    }
+  }
+  /// Receives the next packet and fails if it is not of type `P`
+  pub async fn expect<P: OutgoingPacket + 'static>(&mut self) -> Result<P, ClientError> {
+    let packet = self.receive().await?;
+    match packet.clone().into_any().downcast::<P>() {
+      Ok(expected) => Ok(*expected),
+      Err(_) => Err(ClientError::UnexpectedPacket(packet)),
+    }
+  }
+  /// Closes the connection
+  pub async fn shutdown(mut self) -> Result<(), ClientError> {
+    self.stream.shutdown().await?;
+    Ok(())
+  }
+}
+
+impl From<io::Error> for ClientError {
+  fn from(e: io::Error) -> Self {
+    Self::Io(e)
+  }
+}
+
+impl From<DecodeError> for ClientError {
+  fn from(e: DecodeError) -> Self {
+    Self::Decode(e)
+  }
+}
+
+impl fmt::Display for ClientError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Io(e) => write!(f, "{}", e),
+      Self::Decode(e) => write!(f, "{}", e),
+      Self::Disconnected(packet) => {
+        write!(f, "disconnected ({:?}): {}", packet.reason, packet.message)
+      }
+      Self::UnexpectedPacket(packet) => write!(f, "unexpected packet {:?}", packet),
+      Self::WrongState { expected, actual } => write!(
+        f,
+        "packet requires state {:?}, but the connection is in state {:?}",
+        expected, actual
+      ),
+    }
+  }
+}
+
+impl std::error::Error for ClientError {}
+
+// TLS configurations
+
+/// Trusts the given CA certificates
+pub fn trusting(certificates: &[Certificate]) -> Result<Arc<ClientConfig>, webpki::Error> {
+  let mut config = ClientConfig::new();
+  for certificate in certificates {
+    config.root_store.add(certificate)?;
+  }
+  Ok(Arc::new(config))
+}
+
+/// Accepts only the given certificates, e.g. the self-signed certificate
+/// of a development server. The domain name is not checked.
+pub fn pinned(certificates: Vec<Certificate>) -> Arc<ClientConfig> {
+  let mut config = ClientConfig::new();
+  config
+    .dangerous()
+    .set_certificate_verifier(Arc::new(Pinned(certificates)));
+  Arc::new(config)
+}
+
+/// Accepts any certificate. Only use this for testing!
+pub fn insecure() -> Arc<ClientConfig> {
+  let mut config = ClientConfig::new();
+  config
+    .dangerous()
+    .set_certificate_verifier(Arc::new(NoVerification));
+  Arc::new(config)
+}
+
+struct NoVerification;
+
+impl rustls::ServerCertVerifier for NoVerification {
+  fn verify_server_cert(
+    &self,
+    _roots: &rustls::RootCertStore,
+    _presented_certs: &[Certificate],
+    _dns_name: webpki::DNSNameRef,
+    _ocsp_response: &[u8],
+  ) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
+    Ok(rustls::ServerCertVerified::assertion())
+  }
+}
+
+struct Pinned(Vec<Certificate>);
+
+impl rustls::ServerCertVerifier for Pinned {
+  fn verify_server_cert(
+    &self,
+    _roots: &rustls::RootCertStore,
+    presented_certs: &[Certificate],
+    _dns_name: webpki::DNSNameRef,
+    _ocsp_response: &[u8],
+  ) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
+    match presented_certs.first() {
+      Some(certificate) if self.0.contains(certificate) => {
+        Ok(rustls::ServerCertVerified::assertion())
+      }
+      _ => Err(rustls::TLSError::General("certificate is not pinned".into())),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::packet::serial::SerialWrite;
+
+  fn chat_message() -> Vec<u8> {
+    let mut body = Vec::new();
+    ChatMessagePacket {
+      sender: "anna".into(),
+      message: "hello".into(),
+    }
+    .write_consume(&mut body);
+    body
+  }
+
+  fn decode_err(id: u16, state: State, body: &[u8]) -> DecodeErrorKind {
+    match ClientBoundPacket::decode(id, state, body) {
+      Ok(packet) => panic!("decoded malformed packet {:?}", packet),
+      Err(e) => e.kind,
+    }
+  }
+
+  #[test]
+  fn decodes_packet() {
+    let packet = ClientBoundPacket::decode(2, State::Game, &chat_message()).unwrap();
+    match packet {
+      ClientBoundPacket::ChatMessage(packet) => {
+        assert_eq!(packet.sender, "anna");
+        assert_eq!(packet.message, "hello");
+      }
+      packet => panic!("decoded wrong packet {:?}", packet),
+    }
+  }
+
+  #[test]
+  fn rejects_truncated_body() {
+    let body = chat_message();
+    for len in 0..body.len() {
+      let kind = decode_err(2, State::Game, &body[..len]);
+      assert_eq!(kind, DecodeErrorKind::UnexpectedEnd, "length {}", len);
+    }
+  }
+
+  #[test]
+  fn rejects_trailing_bytes() {
+    let mut body = chat_message();
+    let len = body.len();
+    body.extend(&[0, 0]);
+    assert_eq!(
+      decode_err(2, State::Game, &body),
+      DecodeErrorKind::TrailingBytes
+    );
+
+    // The offset points at the first trailing byte
+    let e = ClientBoundPacket::decode(2, State::Game, &body)
+      .unwrap_err()
+      .in_packet(2, State::Game, body.len());
+    assert_eq!(e.offset, len);
+    assert_eq!(e.field, None);
+  }
+
+  #[test]
+  fn rejects_unknown_packet() {
+    assert_eq!(
+      decode_err(0xFF, State::Game, &[]),
+      DecodeErrorKind::UnknownPacket
+    );
+    // Chat messages are only sent in games
+    let kind = decode_err(2, State::Ping, &chat_message());
+    assert_eq!(kind, DecodeErrorKind::UnknownPacket);
+  }
+}
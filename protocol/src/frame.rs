@@ -0,0 +1,42 @@
+//! Framing of packets. Every packet starts with a header containing the
+//! packet ID and the length of the body that follows.
+
+use std::convert::TryInto;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::packet::serial::SerialWrite;
+
+/// The packet header is always 6 bytes long
+pub const HEADER_LEN: usize = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+  pub packet_id: u16,
+  pub body_len: u32,
+}
+
+impl Header {
+  /// Parses a header (packet id [2 bytes] + body length [4 bytes])
+  pub fn parse(bytes: &[u8; HEADER_LEN]) -> Self {
+    Self {
+      packet_id: LittleEndian::read_u16(&bytes[..2]),
+      body_len: LittleEndian::read_u32(&bytes[2..]),
+    }
+  }
+}
+
+/// Encodes a packet including its header
+pub fn encode<P: SerialWrite>(packet_id: u16, packet: P) -> Vec<u8> {
+  let mut buf = Vec::with_capacity(40);
+  SerialWrite::write_consume(packet_id, &mut buf);
+  // The body length is filled in once the body is written
+  SerialWrite::write_consume(0u32, &mut buf);
+  SerialWrite::write_consume(packet, &mut buf);
+
+  let body_len: u32 = (buf.len() - HEADER_LEN)
+    .try_into()
+    .expect("Packet is too large!");
+  LittleEndian::write_u32(&mut buf[2..HEADER_LEN], body_len);
+  buf
+}
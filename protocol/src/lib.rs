@@ -0,0 +1,15 @@
+//! The Haendlerspiel-Protocol (HSP): packets, their serialization and an
+//! async client. See `net_traffic.md` for the specification.
+
+// Lets the derive macros refer to this crate by name from inside it
+extern crate self as haendler_protocol;
+
+pub mod client;
+pub mod frame;
+pub mod packet;
+pub mod permission_level;
+mod state;
+
+pub use client::HspClient;
+pub use permission_level::PermissionLevel;
+pub use state::State;
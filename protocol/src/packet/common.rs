@@ -0,0 +1,60 @@
+//! Packets that are valid in every state
+
+use super::{
+  packet,
+  serial::{SerialRead, SerialWrite},
+  DecodeErrorKind, PermissionLevel,
+};
+
+#[packet(id = 0xFFFE, state = Any, bound = client)]
+#[derive(Clone, Debug)]
+pub struct PermissionDeniedPacket {
+  pub packet_id: u16,
+  pub required: PermissionLevel,
+}
+
+/// Last packet sent before the server closes a connection
+#[packet(id = 0xFFFF, state = Any, bound = client)]
+#[derive(Clone, Debug)]
+pub struct DisconnectPacket {
+  pub reason: DisconnectReason,
+  /// Human-readable explanation
+  #[serial(str)]
+  pub message: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, SerialRead, SerialWrite)]
+pub enum DisconnectReason {
+  ProtocolError = 0,
+  Kicked = 1,
+  Banned = 2,
+  ServerShutdown = 3,
+  IdleTimeout = 4,
+  UnsupportedVersion = 5,
+  /// The client didn't receive its packets fast enough
+  SlowConnection = 6,
+  /// The server reached its maximum number of connections
+  ServerFull = 7,
+}
+
+/// Sent periodically by the server, clients must echo it
+#[packet(id = 0xFFFC, state = Any, bound = both, permission = Guest)]
+#[derive(Clone, Debug)]
+pub struct HeartbeatPacket {
+  pub id: u32,
+  /// Round-trip time last measured by the server in microseconds,
+  /// 0 if there is no measurement yet
+  pub rtt: u32,
+}
+
+/// Describes why a server bound packet could not be decoded
+#[packet(id = 0xFFFD, state = Any, bound = client)]
+#[derive(Clone, Debug)]
+pub struct DecodeErrorPacket {
+  pub packet_id: u16,
+  pub kind: DecodeErrorKind,
+  pub offset: u32,
+  /// Empty if the field is unknown
+  #[serial(str)]
+  pub field: String,
+}
@@ -0,0 +1,166 @@
+//! Errors that occur while decoding packets in either direction
+
+use std::fmt;
+
+use super::serial::{SerialRead, SerialWrite};
+use crate::State;
+
+/// Reason why a packet could not be decoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, SerialRead, SerialWrite)]
+pub enum DecodeErrorKind {
+  /// No packet with this ID exists in the current state
+  UnknownPacket = 0,
+  /// The body ended before the value was complete
+  UnexpectedEnd = 1,
+  /// A string is not valid UTF-8
+  InvalidUtf8 = 2,
+  /// An enum value is out of range
+  InvalidEnumValue = 3,
+  /// The body is longer than the packet
+  TrailingBytes = 4,
+}
+
+/// Error while decoding a packet, sent by a client or by the server
+#[derive(Clone, Debug)]
+pub struct DecodeError {
+  pub kind: DecodeErrorKind,
+  /// Name of the field that could not be decoded
+  pub field: Option<&'static str>,
+  /// Packet ID and connection state, set by `in_packet`
+  pub packet: Option<(u16, State)>,
+  /// Byte offset of the error in the packet body, set by `in_packet`
+  pub offset: usize,
+  /// Number of unread bytes when the error occurred
+  remaining: usize,
+}
+
+/// Adds the field name to a decode error
+pub trait DecodeContext {
+  fn field(self, name: &'static str) -> Self;
+}
+
+// Implementations
+
+impl DecodeError {
+  /// Creates a new error, `remaining` is the number of bytes left
+  /// in the body starting at the faulty value
+  pub fn new(kind: DecodeErrorKind, remaining: usize) -> Self {
+    Self {
+      kind,
+      field: None,
+      packet: None,
+      offset: 0,
+      remaining,
+    }
+  }
+  /// Sets the packet this error occurred in and resolves the offset
+  pub fn in_packet(mut self, packet_id: u16, state: State, body_len: usize) -> Self {
+    self.packet = Some((packet_id, state));
+    self.offset = body_len.saturating_sub(self.remaining);
+    self
+  }
+}
+
+impl<T> DecodeContext for Result<T, DecodeError> {
+  fn field(self, name: &'static str) -> Self {
+    self.map_err(|mut e| {
+      // The innermost field is the most precise one
+      e.field.get_or_insert(name);
+      e
+    })
+  }
+}
+
+impl fmt::Display for DecodeErrorKind {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Self::UnknownPacket => "unknown packet",
+      Self::UnexpectedEnd => "unexpected end of packet",
+      Self::InvalidUtf8 => "invalid UTF-8",
+      Self::InvalidEnumValue => "invalid enum value",
+      Self::TrailingBytes => "trailing bytes",
+    })
+  }
+}
+
+impl fmt::Display for DecodeError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.kind)?;
+    if let Some(field) = self.field {
+      write!(f, " in field `{}`", field)?;
+    }
+    match self.packet {
+      Some((id, state)) => write!(
+        f,
+        " at byte {} of packet {:#X} ({:?})",
+        self.offset, id, state
+      ),
+      None => Ok(()),
+    }
+  }
+}
+
+impl std::error::Error for DecodeError {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::packet::{HandshakePacket, LoginPacket, RenameGamePacket};
+
+  /// Reads a packet and resolves the error like the receiver
+  fn error<T: SerialRead + fmt::Debug>(body: &[u8]) -> DecodeError {
+    let mut data = body;
+    match T::read(&mut data) {
+      Ok(packet) => panic!("decoded malformed packet {:?}", packet),
+      Err(e) => e.in_packet(1, State::Login, body.len()),
+    }
+  }
+
+  #[test]
+  fn truncated_string() {
+    // The password claims 9 bytes, but only 3 follow
+    let body = [4, b'a', b'n', b'n', b'a', 9, b'p', b'w', b'd'];
+    let e = error::<LoginPacket>(&body);
+    assert_eq!(e.kind, DecodeErrorKind::UnexpectedEnd);
+    assert_eq!(e.field, Some("password"));
+    assert_eq!(e.offset, 5);
+    assert_eq!(e.packet, Some((1, State::Login)));
+  }
+
+  #[test]
+  fn invalid_utf8() {
+    let mut body = vec![0; 8];
+    body.extend(&[2, 0xC3, 0x28]);
+    let e = error::<RenameGamePacket>(&body);
+    assert_eq!(e.kind, DecodeErrorKind::InvalidUtf8);
+    assert_eq!(e.field, Some("name"));
+    assert_eq!(e.offset, 8);
+  }
+
+  #[test]
+  fn truncated_integer() {
+    let e = error::<RenameGamePacket>(&[1, 2, 3]);
+    assert_eq!(e.kind, DecodeErrorKind::UnexpectedEnd);
+    assert_eq!(e.field, Some("id"));
+    assert_eq!(e.offset, 0);
+  }
+
+  #[test]
+  fn unknown_enum_value() {
+    // Version 2, action 7, no capabilities
+    let body = [2, 0, 7, 0, 0, 0, 0];
+    let e = error::<HandshakePacket>(&body);
+    assert_eq!(e.kind, DecodeErrorKind::InvalidEnumValue);
+    assert_eq!(e.field, Some("action"));
+    assert_eq!(e.offset, 2);
+  }
+
+  #[test]
+  fn display() {
+    let e = error::<HandshakePacket>(&[2, 0, 7, 0, 0, 0, 0]);
+    assert_eq!(
+      e.to_string(),
+      "invalid enum value in field `action` at byte 2 of packet 0x1 (Login)"
+    );
+  }
+}
@@ -0,0 +1,59 @@
+use super::{
+  packet,
+  serial::{SerialRead, SerialWrite},
+};
+
+// Structures
+
+/// Ingoing packets of the `Game` state that are handled by the game actor
+#[derive(Debug)]
+pub enum GamePacket {
+  RequestGameInfo(RequestGameInfoPacket),
+  Chat(ChatPacket),
+}
+
+#[packet(id = 0, state = Game, bound = server, permission = Guest)]
+#[derive(Clone, Debug)]
+pub struct LeaveGamePacket {}
+
+#[packet(id = 0, state = Game, bound = client)]
+#[derive(Clone, Debug)]
+pub struct LeftGamePacket {
+  pub reason: LeaveReason,
+}
+
+/// Reason why a player is no longer part of a game
+#[derive(Clone, Copy, Debug, PartialEq, Eq, SerialRead, SerialWrite)]
+pub enum LeaveReason {
+  Left = 0,
+  GameDeleted = 1,
+}
+
+#[packet(id = 1, state = Game, bound = server, permission = Guest)]
+#[derive(Clone, Debug)]
+pub struct RequestGameInfoPacket {}
+
+#[packet(id = 1, state = Game, bound = client)]
+#[derive(Clone, Debug)]
+pub struct GameInfoPacket {
+  pub id: u64,
+  #[serial(name)]
+  pub name: String,
+  pub players: u32,
+}
+
+#[packet(id = 2, state = Game, bound = server, permission = Guest)]
+#[derive(Clone, Debug)]
+pub struct ChatPacket {
+  #[serial(str)]
+  pub message: String,
+}
+
+#[packet(id = 2, state = Game, bound = client)]
+#[derive(Clone, Debug)]
+pub struct ChatMessagePacket {
+  #[serial(name)]
+  pub sender: String,
+  #[serial(str)]
+  pub message: String,
+}
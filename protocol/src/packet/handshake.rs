@@ -0,0 +1,48 @@
+use super::{
+  packet,
+  serial::{SerialRead, SerialWrite},
+};
+
+/// Version of the protocol implemented by this server
+pub const PROTOCOL_VERSION: u16 = 2;
+
+#[packet(id = 0, state = Handshake, bound = server, permission = Guest)]
+#[derive(Clone, Debug)]
+pub struct HandshakePacket {
+  pub protocol_version: u16,
+  pub action: HandshakeAction,
+  pub capabilities: Capabilities,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, SerialRead, SerialWrite)]
+pub enum HandshakeAction {
+  Ping = 1,
+  Connect = 2,
+}
+
+/// Confirms the handshake and the negotiated capabilities
+#[packet(id = 0, state = Handshake, bound = client)]
+#[derive(Clone, Debug)]
+pub struct HandshakeResponsePacket {
+  pub protocol_version: u16,
+  pub capabilities: Capabilities,
+}
+
+/// Set of optional protocol features. Only features supported by
+/// both the client and the server are used.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, SerialRead, SerialWrite)]
+pub struct Capabilities(pub u32);
+
+impl Capabilities {
+  /// The client wants to receive Decode Error packets (debug builds only)
+  pub const DECODE_ERRORS: Self = Self(1 << 0);
+  /// All capabilities supported by this server
+  pub const SUPPORTED: Self = Self(Self::DECODE_ERRORS.0);
+
+  pub fn contains(self, other: Self) -> bool {
+    self.0 & other.0 == other.0
+  }
+  pub fn intersection(self, other: Self) -> Self {
+    Self(self.0 & other.0)
+  }
+}
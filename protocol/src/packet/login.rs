@@ -0,0 +1,150 @@
+use super::{
+  packet,
+  serial::{read_string, write_string, SerialRead, SerialWrite},
+  DecodeError, DecodeErrorKind, PermissionLevel,
+};
+
+// Structures
+
+#[packet(id = 0, state = Login, bound = client)]
+#[derive(Clone, Debug)]
+pub struct ListGamesPacket {
+  #[serial(list)]
+  pub entries: Vec<ListGamesEntry>,
+}
+
+#[derive(Clone, Debug)]
+pub enum ListGamesEntry {
+  Add { id: u64, name: String, players: u32 },
+  Remove { id: u64 },
+}
+
+#[packet(id = 0, state = Login, bound = server, permission = Guest)]
+#[derive(Clone, Debug)]
+pub struct SyncGamesPacket {}
+
+#[packet(id = 1, state = Login, bound = server, permission = Guest)]
+#[derive(Clone, Debug)]
+pub struct LoginPacket {
+  #[serial(name)]
+  pub username: String,
+  #[serial(name)]
+  pub password: String,
+}
+
+#[packet(id = 1, state = Login, bound = client)]
+#[derive(Clone, Debug)]
+pub struct LoginResponsePacket {
+  pub permission_level: PermissionLevel,
+}
+
+#[packet(id = 2, state = Login, bound = server, permission = Moderator)]
+#[derive(Clone, Debug)]
+pub struct CreateGamePacket {
+  #[serial(name)]
+  pub name: String,
+}
+
+#[packet(id = 3, state = Login, bound = server, permission = Moderator)]
+#[derive(Clone, Debug)]
+pub struct DeleteGamePacket {
+  pub id: u64,
+}
+
+#[packet(id = 4, state = Login, bound = server, permission = Moderator)]
+#[derive(Clone, Debug)]
+pub struct RenameGamePacket {
+  pub id: u64,
+  #[serial(name)]
+  pub name: String,
+}
+
+/// Result of creating, deleting or renaming a game
+#[derive(Clone, Copy, Debug, PartialEq, Eq, SerialRead, SerialWrite)]
+pub enum GameActionStatus {
+  Success = 0,
+  NotFound = 1,
+  InvalidName = 2,
+}
+
+#[packet(id = 2, state = Login, bound = client)]
+#[derive(Clone, Debug)]
+pub struct CreateGameResponsePacket {
+  pub status: GameActionStatus,
+  pub id: u64,
+}
+
+#[packet(id = 3, state = Login, bound = client)]
+#[derive(Clone, Debug)]
+pub struct DeleteGameResponsePacket {
+  pub status: GameActionStatus,
+  pub id: u64,
+}
+
+#[packet(id = 4, state = Login, bound = client)]
+#[derive(Clone, Debug)]
+pub struct RenameGameResponsePacket {
+  pub status: GameActionStatus,
+  pub id: u64,
+}
+
+#[packet(id = 5, state = Login, bound = server, permission = Guest)]
+#[derive(Clone, Debug)]
+pub struct JoinGamePacket {
+  pub id: u64,
+}
+
+#[packet(id = 5, state = Login, bound = client)]
+#[derive(Clone, Debug)]
+pub struct JoinGameResponsePacket {
+  pub status: GameActionStatus,
+  pub id: u64,
+}
+
+// Implementations
+
+impl ListGamesEntry {
+  pub fn id(&self) -> u64 {
+    match self {
+      Self::Add { id, .. } | Self::Remove { id } => *id,
+    }
+  }
+}
+
+// The serial traits are written by hand, because the id of the game comes
+// before the tag of the variant. The derive always writes the tag first.
+
+impl SerialRead for ListGamesEntry {
+  fn read(data: &mut &[u8]) -> Result<Self, DecodeError> {
+    let id = u64::read(data)?;
+    match u8::read(data)? {
+      0 => Ok(Self::Add {
+        id,
+        name: read_string::<u8>(data)?,
+        players: u32::read(data)?,
+      }),
+      1 => Ok(Self::Remove { id }),
+      _ => Err(DecodeError::new(
+        DecodeErrorKind::InvalidEnumValue,
+        data.len() + 1,
+      )),
+    }
+  }
+}
+
+impl SerialWrite for ListGamesEntry {
+  fn write_consume(self, buf: &mut Vec<u8>) {
+    match self {
+      Self::Add { id, name, players } => {
+        SerialWrite::write_consume(id, buf);
+        SerialWrite::write_consume(0u8, buf);
+        write_string::<u8>(name, buf);
+        SerialWrite::write_consume(players, buf);
+      }
+      Self::Remove { id } => {
+        SerialWrite::write_consume(id, buf);
+        SerialWrite::write_consume(1u8, buf);
+      }
+    }
+  }
+}
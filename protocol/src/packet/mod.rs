@@ -0,0 +1,33 @@
+pub mod common;
+pub mod error;
+pub mod game;
+pub mod handshake;
+pub mod login;
+pub mod ping;
+
+pub mod serial;
+
+pub use common::*;
+pub use error::*;
+pub use game::*;
+pub use handshake::*;
+pub use login::*;
+pub use ping::*;
+
+pub use haendler_derive::packet;
+
+use crate::{PermissionLevel, State};
+
+/// Packet sent by clients to the server
+pub trait IngoingPacket: serial::SerialRead + serial::SerialWrite {
+    const ID: u16;
+    const STATE: State;
+    /// Minimum permission level a connection needs to send this packet
+    const PERMISSION: PermissionLevel;
+}
+
+/// Packet sent by the server to clients
+pub trait OutgoingPacket: serial::SerialRead + serial::SerialWrite {
+    const ID: u16;
+    const STATE: State;
+}
@@ -0,0 +1,16 @@
+use super::packet;
+
+#[packet(id = 0, state = Ping, bound = client)]
+#[derive(Clone, Debug)]
+pub struct PingStatusPacket {
+  pub players: u32,
+  pub games: u32,
+  #[serial(str)]
+  pub status: String,
+}
+
+#[packet(id = 1, state = Ping, bound = both, permission = Guest)]
+#[derive(Clone, Debug)]
+pub struct PingPongPacket {
+  pub random: u64,
+}
@@ -0,0 +1,361 @@
+//! Module containing helper implementations for reading and writing data in serial
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use super::error::{DecodeError, DecodeErrorKind};
+
+pub use haendler_derive::{SerialRead, SerialWrite};
+
+pub trait SerialWrite: Sized + Clone {
+  fn write(&self, buf: &mut Vec<u8>) {
+    self.clone().write_consume(buf);
+  }
+  fn write_consume(self, buf: &mut Vec<u8>) {
+    self.write(buf);
+  }
+}
+
+pub trait SerialRead: Sized {
+  fn read(data: &mut &[u8]) -> Result<Self, DecodeError>;
+}
+
+// Writing
+
+impl SerialWrite for u8 {
+  fn write_consume(self, buf: &mut Vec<u8>) {
+    buf.push(self);
+  }
+}
+
+impl SerialWrite for i8 {
+  fn write_consume(self, buf: &mut Vec<u8>) {
+    buf.push(self as u8);
+  }
+}
+
+impl SerialWrite for f32 {
+  fn write_consume(self, buf: &mut Vec<u8>) {
+    buf.write_f32::<LittleEndian>(self).unwrap();
+  }
+}
+
+impl SerialWrite for f64 {
+  fn write_consume(self, buf: &mut Vec<u8>) {
+    buf.write_f64::<LittleEndian>(self).unwrap();
+  }
+}
+
+impl SerialWrite for &'_ [u8] {
+  fn write_consume(self, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(self);
+  }
+}
+
+macro_rules! impl_write {
+  ($T:ty) => {
+    impl SerialWrite for $T {
+      fn write_consume(self, buf: &mut Vec<u8>) {
+        buf.extend(self.to_le_bytes().iter());
+      }
+    }
+  };
+  ($($T:ty),*) => {
+    $(
+      impl_write!($T);
+    )*
+  }
+}
+
+impl_write!(u16, u32, u64, u128);
+impl_write!(i16, i32, i64, i128);
+
+// Reading
+
+impl SerialRead for u8 {
+  fn read(data: &mut &[u8]) -> Result<Self, DecodeError> {
+    if !data.is_empty() {
+      let byte = data[0];
+      *data = &data[1..];
+      Ok(byte)
+    } else {
+      Err(DecodeError::new(DecodeErrorKind::UnexpectedEnd, 0))
+    }
+  }
+}
+
+impl SerialRead for i8 {
+  fn read(data: &mut &[u8]) -> Result<Self, DecodeError> {
+    let byte: u8 = SerialRead::read(data)?;
+    Ok(byte as i8)
+  }
+}
+
+macro_rules! impl_write {
+  ($T:ty : $w:literal : $r:ident) => {
+    impl SerialRead for $T {
+      fn read(data: &mut &[u8]) -> Result<Self, DecodeError> {
+        if data.len() >= $w {
+          if let Ok(res) = ReadBytesExt::$r::<LittleEndian>(data) {
+            return Ok(res);
+          }
+        }
+        Err(DecodeError::new(DecodeErrorKind::UnexpectedEnd, data.len()))
+      }
+    }
+  };
+  ($($T:ty : $w:literal : $r:ident),*) => {
+    $(
+      impl_write!($T : $w : $r);
+    )*
+  }
+}
+
+impl_write!(u16:2:read_u16, u32:4:read_u32, u64:8:read_u64, u128:16:read_u128);
+impl_write!(i16:2:read_i16, i32:4:read_i32, i64:8:read_i64, i128:16:read_i128);
+impl_write!(f32:4:read_f32, f64:8:read_f64);
+
+// Length prefixed data
+
+/// Unsigned integer type used as the length prefix of strings and lists
+pub trait LengthPrefix: SerialRead + SerialWrite {
+  fn from_len(len: usize) -> Option<Self>;
+  fn into_len(self) -> usize;
+}
+
+macro_rules! impl_length_prefix {
+  ($($T:ty),*) => {
+    $(
+      impl LengthPrefix for $T {
+        fn from_len(len: usize) -> Option<Self> {
+          std::convert::TryInto::try_into(len).ok()
+        }
+        fn into_len(self) -> usize {
+          std::convert::TryInto::try_into(self).unwrap_or(usize::MAX)
+        }
+      }
+    )*
+  }
+}
+
+impl_length_prefix!(u8, u16, u32, u64);
+
+/// Reads a UTF-8 string prefixed with its length in bytes
+pub fn read_string<L: LengthPrefix>(data: &mut &[u8]) -> Result<String, DecodeError> {
+  // Errors point at the start of the string, including its prefix
+  let remaining = data.len();
+  let len = L::read(data)?.into_len();
+  if data.len() < len {
+    // Truncated string
+    return Err(DecodeError::new(DecodeErrorKind::UnexpectedEnd, remaining));
+  }
+  let (s_data, rest) = data.split_at(len);
+  *data = rest;
+  String::from_utf8(Vec::from(s_data))
+    .map_err(|_| DecodeError::new(DecodeErrorKind::InvalidUtf8, remaining))
+}
+
+pub fn write_string<L: LengthPrefix>(s: String, buf: &mut Vec<u8>) {
+  L::from_len(s.len())
+    .expect("String length exceeds limits of its length prefix")
+    .write_consume(buf);
+  buf.extend(s.into_bytes());
+}
+
+/// Reads a list prefixed with its number of elements
+pub fn read_list<L: LengthPrefix, T: SerialRead>(data: &mut &[u8]) -> Result<Vec<T>, DecodeError> {
+  let len = L::read(data)?.into_len();
+  // Every element takes at least one byte, unless it's zero-sized,
+  // so the remaining data limits the capacity
+  if std::mem::size_of::<T>() == 0 && len > data.len() {
+    // Don't loop over a huge number of zero-sized elements
+    return Err(DecodeError::new(DecodeErrorKind::UnexpectedEnd, data.len()));
+  }
+  let mut vec: Vec<T> = Vec::with_capacity(len.min(data.len()));
+  for _ in 0..len {
+    vec.push(SerialRead::read(data)?);
+  }
+  Ok(vec)
+}
+
+pub fn write_list<L: LengthPrefix, T: SerialWrite>(list: Vec<T>, buf: &mut Vec<u8>) {
+  L::from_len(list.len())
+    .expect("Vec length exceeds limits of its length prefix")
+    .write_consume(buf);
+  for e in list {
+    SerialWrite::write_consume(e, buf);
+  }
+}
+
+// Strings
+
+macro_rules! impl_string {
+  ($T:ident , $LT:ty) => {
+    #[derive(Clone)]
+    pub struct $T {
+      inner: String,
+    }
+
+    impl From<String> for $T {
+      fn from(s: String) -> Self {
+        Self { inner: s }
+      }
+    }
+
+    impl From<$T> for String {
+      fn from(s: $T) -> Self {
+        s.inner
+      }
+    }
+
+    impl std::ops::Deref for $T {
+      type Target = String;
+      fn deref(&self) -> &String {
+        &self.inner
+      }
+    }
+
+    impl SerialRead for $T {
+      fn read(data: &mut &[u8]) -> Result<Self, DecodeError> {
+        read_string::<$LT>(data).map(Self::from)
+      }
+    }
+
+    impl SerialWrite for $T {
+      fn write_consume(self, buf: &mut Vec<u8>) {
+        write_string::<$LT>(self.inner, buf);
+      }
+    }
+  };
+}
+
+impl_string!(PacketString, u32);
+impl_string!(PacketNameString, u8);
+
+// Lists
+
+#[derive(Clone)]
+pub struct PacketList<T> {
+  inner: Vec<T>,
+}
+
+impl<T> From<Vec<T>> for PacketList<T> {
+  fn from(inner: Vec<T>) -> Self {
+    Self { inner }
+  }
+}
+
+impl<T> From<PacketList<T>> for Vec<T> {
+  fn from(s: PacketList<T>) -> Self {
+    s.inner
+  }
+}
+
+impl<T> std::ops::Deref for PacketList<T> {
+  type Target = Vec<T>;
+  fn deref(&self) -> &Vec<T> {
+    &self.inner
+  }
+}
+
+impl<T: SerialRead> SerialRead for PacketList<T> {
+  fn read(data: &mut &[u8]) -> Result<Self, DecodeError> {
+    read_list::<u32, T>(data).map(Self::from)
+  }
+}
+
+impl<T: SerialWrite> SerialWrite for PacketList<T> {
+  fn write_consume(self, buf: &mut Vec<u8>) {
+    write_list::<u32, T>(self.inner, buf);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Zero-sized element, reading it consumes nothing
+  #[derive(Clone, Debug, SerialRead)]
+  struct Empty;
+
+  fn kind<T>(result: Result<T, DecodeError>) -> DecodeErrorKind {
+    match result {
+      Ok(_) => panic!("decoding malformed data succeeded"),
+      Err(e) => e.kind,
+    }
+  }
+
+  #[test]
+  fn truncated_integers() {
+    assert_eq!(kind(u8::read(&mut &[][..])), DecodeErrorKind::UnexpectedEnd);
+    assert_eq!(
+      kind(u32::read(&mut &[1, 2, 3][..])),
+      DecodeErrorKind::UnexpectedEnd
+    );
+    assert_eq!(
+      kind(u64::read(&mut &[1; 7][..])),
+      DecodeErrorKind::UnexpectedEnd
+    );
+  }
+
+  #[test]
+  fn string_prefix_exceeds_data() {
+    let mut data = &[5, b'a', b'b'][..];
+    assert_eq!(
+      kind(read_string::<u8>(&mut data)),
+      DecodeErrorKind::UnexpectedEnd
+    );
+    let mut data = &[0xFF, 0xFF, 0xFF, 0xFF][..];
+    assert_eq!(
+      kind(read_string::<u32>(&mut data)),
+      DecodeErrorKind::UnexpectedEnd
+    );
+    // The prefix itself is truncated
+    let mut data = &[1, 0][..];
+    assert_eq!(
+      kind(read_string::<u32>(&mut data)),
+      DecodeErrorKind::UnexpectedEnd
+    );
+  }
+
+  #[test]
+  fn string_invalid_utf8() {
+    let mut data = &[2, 0xC3, 0x28][..];
+    assert_eq!(
+      kind(read_string::<u8>(&mut data)),
+      DecodeErrorKind::InvalidUtf8
+    );
+  }
+
+  #[test]
+  fn string_round_trip() {
+    let mut buf = Vec::new();
+    write_string::<u8>("Händler".into(), &mut buf);
+    assert_eq!(buf[0] as usize, "Händler".len());
+    let mut data = &buf[..];
+    assert_eq!(read_string::<u8>(&mut data).unwrap(), "Händler");
+    assert!(data.is_empty());
+  }
+
+  #[test]
+  fn truncated_list() {
+    let mut buf = Vec::new();
+    write_list::<u32, u16>(vec![1, 2, 3], &mut buf);
+    buf.pop();
+    let mut data = &buf[..];
+    assert_eq!(
+      kind(read_list::<u32, u16>(&mut data)),
+      DecodeErrorKind::UnexpectedEnd
+    );
+  }
+
+  #[test]
+  fn list_of_zero_sized_elements() {
+    let mut data = &[0xFF, 0xFF, 0xFF, 0xFF][..];
+    assert_eq!(
+      kind(read_list::<u32, Empty>(&mut data)),
+      DecodeErrorKind::UnexpectedEnd
+    );
+    let mut data = &[2, 0, 0][..];
+    assert_eq!(read_list::<u8, Empty>(&mut data).unwrap().len(), 2);
+  }
+}
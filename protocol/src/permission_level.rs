@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+
+use crate::packet::serial::{SerialRead, SerialWrite};
+
+#[derive(
+  Clone,
+  Copy,
+  Debug,
+  Eq,
+  PartialEq,
+  Ord,
+  PartialOrd,
+  Serialize,
+  Deserialize,
+  SerialRead,
+  SerialWrite,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum PermissionLevel {
+  Guest = 0,
+  Moderator = 1,
+  Admin = 2,
+}
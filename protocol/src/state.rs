@@ -0,0 +1,11 @@
+/// State of a connection. Each packet is only valid in one state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum State {
+  Handshake,
+  Ping,
+  Login,
+  Game,
+  /// Pseudo state of packets that are valid in every state.
+  /// A connection is never in this state.
+  Any,
+}
@@ -0,0 +1,212 @@
+//! Round trips through the derived serial traits
+
+use std::fmt::Debug;
+
+use haendler_protocol::packet::serial::{SerialRead, SerialWrite};
+use haendler_protocol::packet::{packet, DecodeErrorKind, IngoingPacket, OutgoingPacket};
+use haendler_protocol::{PermissionLevel, State};
+
+#[derive(Clone, Debug, PartialEq, SerialRead, SerialWrite)]
+struct Strings {
+  #[serial(name)]
+  name: String,
+  #[serial(str)]
+  text: String,
+}
+
+#[derive(Clone, Debug, PartialEq, SerialRead, SerialWrite)]
+struct Lists {
+  #[serial(list)]
+  default: Vec<u16>,
+  #[serial(list = u8)]
+  short: Vec<u8>,
+  #[serial(list = u64)]
+  long: Vec<Strings>,
+}
+
+#[derive(Clone, Debug, PartialEq, SerialRead, SerialWrite)]
+struct Tuple(u8, #[serial(name)] String, i64);
+
+#[derive(Clone, Copy, Debug, PartialEq, SerialRead, SerialWrite)]
+enum Small {
+  A = 1,
+  B = 200,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, SerialRead, SerialWrite)]
+#[serial(repr = u16)]
+enum Medium {
+  A = 0x0102,
+  B = 0xFFFF,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, SerialRead, SerialWrite)]
+#[serial(repr = u32)]
+enum Large {
+  A = 0x0102_0304,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, SerialRead, SerialWrite)]
+#[serial(repr = u64)]
+enum Huge {
+  A = 7,
+}
+
+#[derive(Clone, Debug, PartialEq, SerialRead, SerialWrite)]
+enum Shape {
+  Empty,
+  Named {
+    #[serial(name)]
+    label: String,
+    size: u32,
+  },
+  Tuple(u16, #[serial(list = u8)] Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq, SerialRead, SerialWrite)]
+#[repr(u8)]
+#[serial(repr = u16)]
+enum Explicit {
+  First = 10,
+  Second { value: u8 },
+  Third(u8) = 20,
+}
+
+#[packet(id = 0x1234, state = Login, bound = server, permission = Moderator)]
+#[derive(Clone, Debug)]
+struct ServerPacket {
+  value: u32,
+}
+
+#[packet(id = 7, state = Game, bound = client)]
+#[derive(Clone, Debug)]
+struct ClientPacket {}
+
+#[packet(id = 8, state = Any, bound = both, permission = Guest)]
+#[derive(Clone, Debug)]
+struct BothPacket {}
+
+/// Writes the value, checks the encoding and reads it back
+fn round_trip<T: SerialRead + SerialWrite + Debug + PartialEq>(value: T, expected: &[u8]) {
+  let mut buf = Vec::new();
+  value.write(&mut buf);
+  assert_eq!(buf, expected);
+  let mut data = &buf[..];
+  assert_eq!(T::read(&mut data).unwrap(), value);
+  assert!(data.is_empty(), "{} bytes left", data.len());
+}
+
+#[test]
+fn strings() {
+  let value = Strings {
+    name: "ab".into(),
+    text: "ä".into(),
+  };
+  round_trip(value, &[2, b'a', b'b', 2, 0, 0, 0, 0xC3, 0xA4]);
+}
+
+#[test]
+fn lists() {
+  let value = Lists {
+    default: vec![1, 0x0203],
+    short: vec![9],
+    long: vec![Strings {
+      name: "".into(),
+      text: "x".into(),
+    }],
+  };
+  #[rustfmt::skip]
+  let expected = [
+    2, 0, 0, 0, 1, 0, 3, 2,
+    1, 9,
+    1, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, b'x',
+  ];
+  round_trip(value, &expected);
+}
+
+#[test]
+fn tuple_struct() {
+  round_trip(
+    Tuple(5, "n".into(), -1),
+    &[5, 1, b'n', 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+  );
+}
+
+#[test]
+fn repr_widths() {
+  round_trip(Small::A, &[1]);
+  round_trip(Small::B, &[200]);
+  round_trip(Medium::A, &[2, 1]);
+  round_trip(Medium::B, &[0xFF, 0xFF]);
+  round_trip(Large::A, &[4, 3, 2, 1]);
+  round_trip(Huge::A, &[7, 0, 0, 0, 0, 0, 0, 0]);
+}
+
+#[test]
+fn variants_with_fields() {
+  round_trip(Shape::Empty, &[0]);
+  let named = Shape::Named {
+    label: "x".into(),
+    size: 3,
+  };
+  round_trip(named, &[1, 1, b'x', 3, 0, 0, 0]);
+  round_trip(Shape::Tuple(0x0102, vec![4, 5]), &[2, 2, 1, 2, 4, 5]);
+}
+
+#[test]
+fn explicit_discriminants() {
+  round_trip(Explicit::First, &[10, 0]);
+  round_trip(Explicit::Second { value: 1 }, &[11, 0, 1]);
+  round_trip(Explicit::Third(2), &[20, 0, 2]);
+}
+
+#[test]
+fn unknown_discriminant() {
+  let mut data = &[3, 1, 2][..];
+  let e = Shape::read(&mut data).unwrap_err();
+  assert_eq!(e.kind, DecodeErrorKind::InvalidEnumValue);
+
+  // The offset points at the discriminant, after the field before it
+  #[derive(Debug, SerialRead)]
+  struct Wrapper {
+    _id: u32,
+    _medium: Medium,
+  }
+  let body = [1, 0, 0, 0, 0x03, 0x01, 9];
+  let e = Wrapper::read(&mut &body[..])
+    .unwrap_err()
+    .in_packet(1, State::Login, body.len());
+  assert_eq!(e.kind, DecodeErrorKind::InvalidEnumValue);
+  assert_eq!(e.field, Some("_medium"));
+  assert_eq!(e.offset, 4);
+}
+
+#[test]
+fn packet_constants() {
+  assert_eq!(<ServerPacket as IngoingPacket>::ID, 0x1234);
+  assert_eq!(<ServerPacket as IngoingPacket>::STATE, State::Login);
+  assert_eq!(
+    <ServerPacket as IngoingPacket>::PERMISSION,
+    PermissionLevel::Moderator
+  );
+
+  assert_eq!(<ClientPacket as OutgoingPacket>::ID, 7);
+  assert_eq!(<ClientPacket as OutgoingPacket>::STATE, State::Game);
+
+  assert_eq!(<BothPacket as IngoingPacket>::ID, 8);
+  assert_eq!(<BothPacket as IngoingPacket>::STATE, State::Any);
+  assert_eq!(
+    <BothPacket as IngoingPacket>::PERMISSION,
+    PermissionLevel::Guest
+  );
+  assert_eq!(<BothPacket as OutgoingPacket>::ID, 8);
+  assert_eq!(<BothPacket as OutgoingPacket>::STATE, State::Any);
+}
+
+#[test]
+fn packet_round_trip() {
+  let mut buf = Vec::new();
+  ServerPacket { value: 0x0A0B0C0D }.write(&mut buf);
+  assert_eq!(buf, [0x0D, 0x0C, 0x0B, 0x0A]);
+  assert_eq!(ServerPacket::read(&mut &buf[..]).unwrap().value, 0x0A0B0C0D);
+}
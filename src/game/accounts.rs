@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+use super::permission_level::PermissionLevel;
+
+// Structures
+
+#[derive(Clone, Debug)]
+pub struct AccountStoreHandle {
+    sender: mpsc::Sender<AccountStoreMessage>,
+}
+
+/// Persistent store of all user accounts, backed by a TOML file.
+#[derive(Debug)]
+pub struct AccountStoreActor {
+    path: PathBuf,
+    accounts: HashMap<String, Account>,
+    /// Hash verified for unknown usernames, so that the time a login
+    /// takes doesn't reveal whether an account exists
+    dummy_hash: String,
+}
+
+#[derive(Debug)]
+enum AccountStoreMessage {
+    StopActor,
+    Login(String, String, oneshot::Sender<Option<PermissionLevel>>),
+}
+
+/// Layout of the account file on disk.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct AccountFile {
+    #[serde(default)]
+    accounts: HashMap<String, Account>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Account {
+    permission_level: PermissionLevel,
+    /// Argon2id hash in the PHC string format
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
+    /// Plain text password of a newly added account. It is replaced
+    /// by a hash as soon as the store is loaded.
+    #[serde(default, skip_serializing)]
+    password: Option<String>,
+}
+
+// Implementations
+
+const ACTOR_DROPPED_ERROR: &str = "AccountStoreActor was dropped, oopsie!";
+
+/// Argon2id parameters (19 MiB memory, 2 passes, 1 lane)
+fn hash_config<'a>() -> argon2::Config<'a> {
+    argon2::Config {
+        variant: argon2::Variant::Argon2id,
+        version: argon2::Version::Version13,
+        mem_cost: 19 * 1024,
+        time_cost: 2,
+        lanes: 1,
+        ..argon2::Config::default()
+    }
+}
+
+fn hash_password(password: &str) -> String {
+    let salt: [u8; 16] = rand::random();
+    argon2::hash_encoded(password.as_bytes(), &salt, &hash_config())
+        .expect("Argon2 configuration is invalid")
+}
+
+impl AccountStoreHandle {
+    pub async fn stop_actor(&mut self) {
+        self.sender
+            .send(AccountStoreMessage::StopActor)
+            .await
+            .expect(ACTOR_DROPPED_ERROR)
+    }
+    /// Verifies the credentials and returns the permission level of the
+    /// account, or `None` if the username or password is wrong.
+    pub async fn login(&mut self, username: String, password: String) -> Option<PermissionLevel> {
+        let (send, recv) = oneshot::channel();
+        self.sender
+            .send(AccountStoreMessage::Login(username, password, send))
+            .await
+            .expect(ACTOR_DROPPED_ERROR);
+        recv.await.expect(ACTOR_DROPPED_ERROR)
+    }
+}
+
+impl AccountStoreActor {
+    /// Loads the account store from a file. A missing file results in an
+    /// empty store. Plain text passwords are hashed and written back.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file: AccountFile = match fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid account file {}: {}", path.display(), e),
+                )
+            })?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                println!(
+                    "(ℹ) Account file {} does not exist, nobody can log in",
+                    path.display()
+                );
+                AccountFile::default()
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut changed = false;
+        for (name, account) in file.accounts.iter_mut() {
+            if let Some(password) = account.password.take() {
+                account.hash = Some(hash_password(&password));
+                changed = true;
+            } else if account.hash.is_none() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("account {:?} has neither a password nor a hash", name),
+                ));
+            }
+        }
+
+        let store = Self {
+            path,
+            accounts: file.accounts,
+            dummy_hash: hash_password(""),
+        };
+        if changed {
+            store.save()?;
+        }
+        Ok(store)
+    }
+    /// Writes all accounts to the file this store was loaded from
+    fn save(&self) -> io::Result<()> {
+        let file = AccountFile {
+            accounts: self.accounts.clone(),
+        };
+        let content = toml::to_string_pretty(&file)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        // Write to a temporary file first, so that a crash can't
+        // leave a half-written account file behind
+        let tmp_path = self.path.with_extension("toml.tmp");
+        fs::write(&tmp_path, content)?;
+        fs::rename(&tmp_path, &self.path)
+    }
+    pub fn spawn(self) -> (AccountStoreHandle, JoinHandle<AccountStoreActor>) {
+        let (send, recv) = mpsc::channel(1024);
+
+        (
+            AccountStoreHandle { sender: send },
+            tokio::spawn(async move { self.actor(recv).await }),
+        )
+    }
+    async fn actor(self, mut recv: mpsc::Receiver<AccountStoreMessage>) -> Self {
+        loop {
+            match recv.recv().await {
+                None => return self,
+                Some(AccountStoreMessage::StopActor) => return self,
+                Some(AccountStoreMessage::Login(username, password, cb)) => {
+                    let (hash, level) = match self.accounts.get(&username) {
+                        Some(Account {
+                            hash: Some(hash),
+                            permission_level,
+                            ..
+                        }) => (hash.clone(), Some(*permission_level)),
+                        // Unknown accounts are rejected after the same work
+                        _ => (self.dummy_hash.clone(), None),
+                    };
+                    // Hashing is slow on purpose, so it must not block this actor
+                    tokio::task::spawn_blocking(move || {
+                        let valid = matches!(
+                            argon2::verify_encoded(&hash, password.as_bytes()),
+                            Ok(true)
+                        );
+                        let _ = cb.send(level.filter(|_| valid));
+                    });
+                }
+            }
+        }
+    }
+}
@@ -1,32 +1,722 @@
-use std::fs::File;
-use std::io::{self, BufReader};
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
 
+use serde::Deserialize;
 use structopt::StructOpt;
 
-use tokio_rustls::rustls::internal::pemfile::{certs, rsa_private_keys};
-use tokio_rustls::rustls::{Certificate, PrivateKey};
+use super::net::packet::PROTOCOL_VERSION;
+use super::net::{HeartbeatConfig, Listener, PacketLimit, PacketLimits, Transport};
+use super::status::ServerStatus;
 
+// Structures
+
+/// Command line options. They override the environment variables, which
+/// override the config file.
 #[derive(StructOpt, Debug)]
 #[structopt(name = "haendlerspiel")]
 pub struct Options {
-  /// Path to TLS certificate
-  #[structopt(short = "C", long = "tls-cert", parse(from_os_str))]
-  cert: PathBuf,
+  /// Path to a TOML config file, see `server.example.toml`
+  #[structopt(short = "c", long = "config", env = "HSP_CONFIG", parse(from_os_str))]
+  config: Option<PathBuf>,
+
+  /// Addresses the server listens on, e.g. `0.0.0.0:25252` or `[::]:25252`.
+  /// May be given multiple times. [default: 127.0.0.1:25252] [env: HSP_BIND]
+  #[structopt(short = "b", long = "bind", number_of_values = 1)]
+  bind: Vec<SocketAddr>,
+
+  /// Addresses of listeners for WebSocket connections, e.g. of browsers.
+  /// Every binary message carries one packet. May be given multiple times.
+  /// [env: HSP_WEBSOCKET]
+  #[structopt(long = "websocket", number_of_values = 1)]
+  websocket: Vec<SocketAddr>,
+
+  /// Path to the PEM encoded TLS certificate chain, starting with the
+  /// certificate of the server. Reloaded on SIGHUP or when it changes.
+  #[structopt(
+    short = "C",
+    long = "tls-cert",
+    env = "HSP_TLS_CERT",
+    parse(from_os_str)
+  )]
+  cert: Option<PathBuf>,
+
+  /// Path to the PEM encoded PKCS#8 (RSA, ECDSA, Ed25519) or RSA private key
+  #[structopt(short = "K", long = "tls-key", env = "HSP_TLS_KEY", parse(from_os_str))]
+  key: Option<PathBuf>,
+
+  /// Accept connections without TLS, e.g. to inspect the traffic.
+  /// Only for development! [env: HSP_PLAINTEXT]
+  #[structopt(long = "plaintext", require_equals = true)]
+  plaintext: Option<Option<bool>>,
+
+  /// Accept plaintext connections on addresses other than loopback ones
+  /// [env: HSP_ALLOW_PUBLIC_PLAINTEXT]
+  #[structopt(long = "allow-public-plaintext", require_equals = true)]
+  allow_public_plaintext: Option<Option<bool>>,
+
+  /// Path to the account file [default: accounts.toml]
+  #[structopt(
+    short = "A",
+    long = "accounts",
+    env = "HSP_ACCOUNTS",
+    parse(from_os_str)
+  )]
+  accounts: Option<PathBuf>,
+
+  /// Message of the day shown in the server list [default: Händlerspiel server]
+  #[structopt(long = "motd", env = "HSP_MOTD")]
+  motd: Option<String>,
+
+  /// Maximum number of players shown in the server list [default: 100]
+  #[structopt(long = "max-players", env = "HSP_MAX_PLAYERS")]
+  max_players: Option<u32>,
+
+  /// Path to a PNG image shown in the server list
+  #[structopt(long = "icon", env = "HSP_ICON", parse(from_os_str))]
+  icon: Option<PathBuf>,
+
+  /// Maximum body size of server bound packets in bytes [default: 65536]
+  #[structopt(long = "max-packet-size", env = "HSP_MAX_PACKET_SIZE")]
+  max_packet_size: Option<u32>,
+
+  /// Maximum body size of all packets in a state or a single packet,
+  /// e.g. `login=1024` or `game:2=512` [env: HSP_PACKET_LIMITS]
+  #[structopt(long = "packet-limit", number_of_values = 1)]
+  packet_limits: Vec<PacketLimit>,
+
+  /// Maximum number of bytes queued for a client. Clients that don't
+  /// receive their packets fast enough are disconnected. [default: 1048576]
+  #[structopt(long = "send-queue-limit", env = "HSP_SEND_QUEUE_LIMIT")]
+  send_queue_limit: Option<usize>,
 
-  /// Path to TLS key
-  #[structopt(short = "K", long = "tls-key", parse(from_os_str))]
-  key: PathBuf,
+  /// Maximum number of open connections. Further connections are refused
+  /// with a Disconnect packet. [default: 1000]
+  #[structopt(long = "max-connections", env = "HSP_MAX_CONNECTIONS")]
+  max_connections: Option<usize>,
+
+  /// Seconds between two heartbeats sent to connections [default: 15]
+  #[structopt(long = "heartbeat-interval", env = "HSP_HEARTBEAT_INTERVAL")]
+  heartbeat_interval: Option<u64>,
+
+  /// Number of unanswered heartbeats after which a connection is closed
+  /// [default: 3]
+  #[structopt(long = "heartbeat-misses", env = "HSP_HEARTBEAT_MISSES")]
+  heartbeat_misses: Option<usize>,
+
+  /// Seconds a connection may take to complete the handshake [default: 10]
+  #[structopt(long = "handshake-timeout", env = "HSP_HANDSHAKE_TIMEOUT")]
+  handshake_timeout: Option<u64>,
+
+  /// Print the contents of all packets sent and received [env: HSP_LOG_PACKETS]
+  #[structopt(long = "log-packets", require_equals = true)]
+  log_packets: Option<Option<bool>>,
 }
 
+/// Layout of the config file. Every setting is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+  server: ServerSection,
+  tls: TlsSection,
+  status: StatusSection,
+  limits: LimitsSection,
+  timeouts: TimeoutsSection,
+  log: LogSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ServerSection {
+  bind: Option<Vec<SocketAddr>>,
+  websocket: Option<Vec<SocketAddr>>,
+  accounts: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TlsSection {
+  cert: Option<PathBuf>,
+  key: Option<PathBuf>,
+  plaintext: Option<bool>,
+  allow_public_plaintext: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct StatusSection {
+  motd: Option<String>,
+  max_players: Option<u32>,
+  icon: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LimitsSection {
+  max_packet_size: Option<u32>,
+  /// Limits of states and packets, e.g. `login = 1024` or `"game:2" = 512`
+  packets: BTreeMap<String, u32>,
+  send_queue: Option<usize>,
+  max_connections: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TimeoutsSection {
+  heartbeat_interval: Option<u64>,
+  heartbeat_misses: Option<usize>,
+  handshake: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LogSection {
+  packets: Option<bool>,
+}
+
+/// Validated configuration of the server, merged from the command line,
+/// the environment and the config file
+#[derive(Debug)]
+pub struct Config {
+  bind: Vec<SocketAddr>,
+  websocket: Vec<SocketAddr>,
+  /// Loaded TLS certificate, unless connections are not encrypted
+  pub transport: Transport,
+  pub accounts: PathBuf,
+  motd: String,
+  max_players: u32,
+  icon: Option<PathBuf>,
+  pub limits: PacketLimits,
+  pub heartbeat: HeartbeatConfig,
+  pub log_packets: bool,
+}
+
+/// All problems found in the configuration
+#[derive(Debug)]
+pub struct ConfigError(Vec<String>);
+
+// Implementations
+
 impl Options {
-  pub fn load(self) -> io::Result<(Vec<Certificate>, PrivateKey)> {
-    Ok((
-      certs(&mut BufReader::new(File::open(self.cert)?))
-        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid certificate"))?,
-      rsa_private_keys(&mut BufReader::new(File::open(self.key)?))
-        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid rsa key"))?
-        .remove(0),
-    ))
+  /// Merges the options with the config file and validates them
+  pub fn load(self) -> Result<Config, ConfigError> {
+    let (file, base) = match &self.config {
+      Some(path) => {
+        let file = ConfigFile::read(path).map_err(|e| ConfigError(vec![e]))?;
+        println!("(ℹ) Loaded config file {}", path.display());
+        // Paths in the file are relative to it
+        (
+          file,
+          path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        )
+      }
+      None => (ConfigFile::default(), PathBuf::new()),
+    };
+    let in_file = |path: Option<PathBuf>| path.map(|path| base.join(path));
+    let mut problems = Vec::new();
+
+    // Listeners
+    let bind = or_env(self.bind, "HSP_BIND", &mut problems);
+    let bind = or_file(bind, file.server.bind)
+      .unwrap_or_else(|| vec![SocketAddr::from(([127, 0, 0, 1], 25252))]);
+    let websocket = or_env(self.websocket, "HSP_WEBSOCKET", &mut problems);
+    let websocket = or_file(websocket, file.server.websocket).unwrap_or_default();
+    if bind.is_empty() && websocket.is_empty() {
+      problems.push("server.bind (--bind): there is no address to listen on".into());
+    }
+    let mut addresses = HashSet::new();
+    for address in bind.iter().chain(&websocket) {
+      if !addresses.insert(address) {
+        problems.push(format!("server: {} is used by multiple listeners", address));
+      }
+    }
+
+    // Transport
+    let plaintext = flag(self.plaintext, "HSP_PLAINTEXT", &mut problems)
+      .or(file.tls.plaintext)
+      .unwrap_or(false);
+    let allow_public_plaintext = flag(
+      self.allow_public_plaintext,
+      "HSP_ALLOW_PUBLIC_PLAINTEXT",
+      &mut problems,
+    )
+    .or(file.tls.allow_public_plaintext)
+    .unwrap_or(false);
+    let cert = self.cert.or(in_file(file.tls.cert));
+    let key = self.key.or(in_file(file.tls.key));
+    let transport = if plaintext {
+      for address in bind.iter().chain(&websocket) {
+        if !address.ip().is_loopback() && !allow_public_plaintext {
+          problems.push(format!(
+            "tls.plaintext (--plaintext): refusing to accept plaintext connections on {}, \
+             which is not a loopback address (override with allow_public_plaintext or \
+             --allow-public-plaintext)",
+            address
+          ));
+        }
+      }
+      Some(Transport::Plaintext)
+    } else {
+      match (cert, key) {
+        (Some(cert), Some(key)) => match Transport::tls(cert, key) {
+          Ok(transport) => Some(transport),
+          Err(e) => {
+            problems.push(format!("tls (--tls-cert, --tls-key): {}", e));
+            None
+          }
+        },
+        _ => {
+          problems.push(
+            "tls (--tls-cert, --tls-key): a certificate and a key are required \
+             unless plaintext connections are accepted"
+              .into(),
+          );
+          None
+        }
+      }
+    };
+
+    // Status
+    let icon = self.icon.or(in_file(file.status.icon));
+    if let Some(icon) = &icon {
+      check_file(&mut problems, "status.icon (--icon)", icon);
+    }
+
+    // Limits
+    let max_packet_size = self
+      .max_packet_size
+      .or(file.limits.max_packet_size)
+      .unwrap_or(64 * 1024);
+    if max_packet_size == 0 {
+      problems.push("limits.max_packet_size (--max-packet-size): must be at least 1".into());
+    }
+    let mut limits = PacketLimits::new(max_packet_size);
+    for (target, size) in &file.limits.packets {
+      match format!("{}={}", target, size).parse() {
+        Ok(limit) => limits.insert(limit),
+        Err(e) => problems.push(format!("limits.packets.{:?}: {}", target, e)),
+      }
+    }
+    for limit in or_env(self.packet_limits, "HSP_PACKET_LIMITS", &mut problems) {
+      limits.insert(limit);
+    }
+    limits.send_queue = self
+      .send_queue_limit
+      .or(file.limits.send_queue)
+      .unwrap_or(limits.send_queue);
+    if limits.send_queue == 0 {
+      problems.push("limits.send_queue (--send-queue-limit): must be at least 1".into());
+    }
+    limits.max_connections = self
+      .max_connections
+      .or(file.limits.max_connections)
+      .unwrap_or(limits.max_connections);
+    if limits.max_connections == 0 {
+      problems.push("limits.max_connections (--max-connections): must be at least 1".into());
+    }
+
+    let log_packets = flag(self.log_packets, "HSP_LOG_PACKETS", &mut problems)
+      .or(file.log.packets)
+      .unwrap_or(false);
+
+    // Timeouts
+    let heartbeat_interval = self
+      .heartbeat_interval
+      .or(file.timeouts.heartbeat_interval)
+      .unwrap_or(15);
+    let heartbeat_misses = self
+      .heartbeat_misses
+      .or(file.timeouts.heartbeat_misses)
+      .unwrap_or(3);
+    let handshake_timeout = self
+      .handshake_timeout
+      .or(file.timeouts.handshake)
+      .unwrap_or(10);
+    if heartbeat_interval == 0 {
+      problems
+        .push("timeouts.heartbeat_interval (--heartbeat-interval): must be at least 1".into());
+    }
+    if heartbeat_misses == 0 {
+      problems.push("timeouts.heartbeat_misses (--heartbeat-misses): must be at least 1".into());
+    }
+    if handshake_timeout == 0 {
+      problems.push("timeouts.handshake (--handshake-timeout): must be at least 1".into());
+    }
+
+    let transport = match transport {
+      Some(transport) if problems.is_empty() => transport,
+      _ => return Err(ConfigError(problems)),
+    };
+    Ok(Config {
+      bind,
+      websocket,
+      transport,
+      accounts: self
+        .accounts
+        .or(in_file(file.server.accounts))
+        .unwrap_or_else(|| "accounts.toml".into()),
+      motd: self
+        .motd
+        .or(file.status.motd)
+        .unwrap_or_else(|| "Händlerspiel server".into()),
+      max_players: self.max_players.or(file.status.max_players).unwrap_or(100),
+      icon,
+      limits,
+      heartbeat: HeartbeatConfig {
+        interval: Duration::from_secs(heartbeat_interval),
+        max_missed: heartbeat_misses,
+        handshake_timeout: Duration::from_secs(handshake_timeout),
+      },
+      log_packets,
+    })
+  }
+}
+
+/// Values of a list option, or else of its environment variable. Clap
+/// would combine both instead of preferring the command line.
+fn or_env<T>(values: Vec<T>, var: &str, problems: &mut Vec<String>) -> Vec<T>
+where
+  T: FromStr,
+  T::Err: fmt::Display,
+{
+  let list = match env::var(var) {
+    Ok(list) if values.is_empty() => list,
+    _ => return values,
+  };
+  let items = list
+    .split(',')
+    .map(str::trim)
+    .filter(|item| !item.is_empty());
+  items
+    .filter_map(|item| match item.parse() {
+      Ok(value) => Some(value),
+      Err(e) => {
+        problems.push(format!("{}: invalid value {:?}: {}", var, item, e));
+        None
+      }
+    })
+    .collect()
+}
+
+/// Value of a flag, which is enabled by `--flag` alone and can be
+/// disabled by `--flag=false`, or else of its environment variable
+fn flag(value: Option<Option<bool>>, var: &str, problems: &mut Vec<String>) -> Option<bool> {
+  if let Some(value) = value {
+    return Some(value.unwrap_or(true));
+  }
+  let value = env::var(var).ok()?;
+  parse_bool(&value)
+    .map_err(|e| problems.push(format!("{}: {}", var, e)))
+    .ok()
+}
+
+fn parse_bool(s: &str) -> Result<bool, String> {
+  match s.trim().to_lowercase().as_str() {
+    "true" | "yes" | "on" | "1" => Ok(true),
+    "false" | "no" | "off" | "0" => Ok(false),
+    _ => Err(format!("invalid value {:?}, expected true or false", s)),
+  }
+}
+
+/// Values given on the command line or in the environment replace the
+/// ones in the file
+fn or_file<T>(values: Vec<T>, file: Option<Vec<T>>) -> Option<Vec<T>> {
+  if values.is_empty() {
+    file
+  } else {
+    Some(values)
+  }
+}
+
+fn check_file(problems: &mut Vec<String>, setting: &str, path: &Path) {
+  if let Err(e) = fs::metadata(path) {
+    problems.push(format!(
+      "{}: can't access {}: {}",
+      setting,
+      path.display(),
+      e
+    ));
+  }
+}
+
+impl ConfigFile {
+  fn read(path: &Path) -> Result<Self, String> {
+    let content = fs::read_to_string(path)
+      .map_err(|e| format!("can't read config file {}: {}", path.display(), e))?;
+    toml::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e))
+  }
+}
+
+impl Config {
+  pub fn status(&self) -> io::Result<ServerStatus> {
+    let icon = match &self.icon {
+      Some(path) => Some(format!(
+        "data:image/png;base64,{}",
+        base64::encode(std::fs::read(path)?)
+      )),
+      None => None,
+    };
+    Ok(ServerStatus {
+      text: self.motd.clone(),
+      version: env!("CARGO_PKG_VERSION").into(),
+      protocol: PROTOCOL_VERSION,
+      max_players: self.max_players,
+      icon,
+    })
+  }
+  /// Addresses to listen on and whether they accept WebSocket connections
+  pub fn addresses(&self) -> Vec<(SocketAddr, bool)> {
+    let tcp = self.bind.iter().map(|address| (*address, false));
+    let websocket = self.websocket.iter().map(|address| (*address, true));
+    tcp.chain(websocket).collect()
+  }
+  /// Binds all addresses
+  pub fn listeners(&self) -> io::Result<Vec<Listener>> {
+    let addresses = self.addresses().into_iter();
+    addresses
+      .map(|(address, websocket)| Listener::bind(address, websocket))
+      .collect()
+  }
+}
+
+impl fmt::Display for ConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "Invalid configuration:")?;
+    for problem in &self.0 {
+      write!(f, "\n  - {}", problem)?;
+    }
+    Ok(())
+  }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::Mutex;
+
+  /// Tests that read or change the environment must not run concurrently
+  static ENV: Mutex<()> = Mutex::new(());
+
+  fn options(args: &[&str]) -> Options {
+    let args = std::iter::once("haendlerspiel").chain(args.iter().copied());
+    Options::from_iter_safe(args).unwrap()
+  }
+
+  /// Creates a directory containing the given files
+  fn directory(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = env::temp_dir().join(format!("hsp-config-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for (file, content) in files {
+      fs::write(dir.join(file), content).unwrap();
+    }
+    dir
+  }
+
+  fn problems(result: Result<Config, ConfigError>) -> Vec<String> {
+    match result {
+      Ok(config) => panic!("invalid configuration was accepted: {:?}", config),
+      Err(ConfigError(problems)) => problems,
+    }
+  }
+
+  #[test]
+  fn or_env_prefers_command_line() {
+    let _env = ENV.lock().unwrap();
+    let mut problems = Vec::new();
+    env::set_var("HSP_TEST_OR_ENV", "1, 2,,3");
+    assert_eq!(or_env(vec![4], "HSP_TEST_OR_ENV", &mut problems), [4]);
+    assert_eq!(
+      or_env(Vec::<u8>::new(), "HSP_TEST_OR_ENV", &mut problems),
+      [1, 2, 3]
+    );
+    env::remove_var("HSP_TEST_OR_ENV");
+    assert!(or_env(Vec::<u8>::new(), "HSP_TEST_OR_ENV", &mut problems).is_empty());
+    assert!(problems.is_empty());
+
+    env::set_var("HSP_TEST_OR_ENV", "1,x");
+    assert_eq!(
+      or_env(Vec::<u8>::new(), "HSP_TEST_OR_ENV", &mut problems),
+      [1]
+    );
+    env::remove_var("HSP_TEST_OR_ENV");
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].starts_with("HSP_TEST_OR_ENV: invalid value \"x\""));
+  }
+
+  #[test]
+  fn or_file_replaces_file() {
+    assert_eq!(or_file(vec![1], Some(vec![2, 3])), Some(vec![1]));
+    assert_eq!(or_file(vec![], Some(vec![2, 3])), Some(vec![2, 3]));
+    assert_eq!(or_file(Vec::<u8>::new(), None), None);
+  }
+
+  #[test]
+  fn flags() {
+    let _env = ENV.lock().unwrap();
+    let mut problems = Vec::new();
+    env::set_var("HSP_TEST_FLAG", "true");
+    assert_eq!(flag(Some(None), "HSP_TEST_FLAG", &mut problems), Some(true));
+    assert_eq!(
+      flag(Some(Some(false)), "HSP_TEST_FLAG", &mut problems),
+      Some(false)
+    );
+    assert_eq!(flag(None, "HSP_TEST_FLAG", &mut problems), Some(true));
+    env::set_var("HSP_TEST_FLAG", "0");
+    assert_eq!(flag(None, "HSP_TEST_FLAG", &mut problems), Some(false));
+    env::remove_var("HSP_TEST_FLAG");
+    assert_eq!(flag(None, "HSP_TEST_FLAG", &mut problems), None);
+    assert!(problems.is_empty());
+
+    env::set_var("HSP_TEST_FLAG", "maybe");
+    assert_eq!(flag(None, "HSP_TEST_FLAG", &mut problems), None);
+    env::remove_var("HSP_TEST_FLAG");
+    assert_eq!(problems.len(), 1);
+  }
+
+  #[test]
+  fn switches_can_be_turned_off() {
+    let _env = ENV.lock().unwrap();
+    let dir = directory("switches", &[("server.toml", "[log]\npackets = true\n")]);
+    let config = dir.join("server.toml");
+    let config = config.to_str().unwrap();
+
+    let loaded = options(&["-c", config, "--plaintext"]).load().unwrap();
+    assert!(loaded.log_packets);
+    let loaded = options(&["-c", config, "--plaintext", "--log-packets=false"]);
+    assert!(!loaded.load().unwrap().log_packets);
+
+    env::set_var("HSP_LOG_PACKETS", "false");
+    let loaded = options(&["-c", config, "--plaintext"]).load().unwrap();
+    assert!(!loaded.log_packets);
+    let loaded = options(&["-c", config, "--plaintext", "--log-packets"])
+      .load()
+      .unwrap();
+    assert!(loaded.log_packets);
+    env::remove_var("HSP_LOG_PACKETS");
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn command_line_overrides_environment_and_file() {
+    let _env = ENV.lock().unwrap();
+    let file = "[status]\nmotd = \"file\"\nmax_players = 5\n\n[server]\nbind = [\"127.0.0.1:1\"]\n";
+    let dir = directory("precedence", &[("server.toml", file)]);
+    let config = dir.join("server.toml");
+    let config = config.to_str().unwrap();
+
+    let loaded = options(&["-c", config, "--plaintext"]).load().unwrap();
+    assert_eq!(loaded.motd, "file");
+    assert_eq!(loaded.bind, [SocketAddr::from(([127, 0, 0, 1], 1))]);
+
+    env::set_var("HSP_MOTD", "env");
+    env::set_var("HSP_BIND", "127.0.0.1:2");
+    let loaded = options(&["-c", config, "--plaintext"]).load().unwrap();
+    assert_eq!(loaded.motd, "env");
+    assert_eq!(loaded.bind, [SocketAddr::from(([127, 0, 0, 1], 2))]);
+
+    let args = [
+      "-c",
+      config,
+      "--plaintext",
+      "--motd",
+      "cli",
+      "-b",
+      "127.0.0.1:3",
+    ];
+    let loaded = options(&args).load().unwrap();
+    env::remove_var("HSP_MOTD");
+    env::remove_var("HSP_BIND");
+    assert_eq!(loaded.motd, "cli");
+    assert_eq!(loaded.bind, [SocketAddr::from(([127, 0, 0, 1], 3))]);
+    // Settings that are not overridden keep the value of the file
+    assert_eq!(loaded.max_players, 5);
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn paths_are_relative_to_the_file() {
+    let _env = ENV.lock().unwrap();
+    let file = "[server]\naccounts = \"accounts.toml\"\n\n[status]\nicon = \"icon.png\"\n";
+    let dir = directory("paths", &[("server.toml", file), ("icon.png", "")]);
+    let config = dir.join("server.toml");
+
+    let loaded = options(&["-c", config.to_str().unwrap(), "--plaintext"]);
+    let loaded = loaded.load().unwrap();
+    assert_eq!(loaded.accounts, dir.join("accounts.toml"));
+    assert_eq!(loaded.icon, Some(dir.join("icon.png")));
+
+    // Paths on the command line are relative to the working directory
+    let args = [
+      "-c",
+      config.to_str().unwrap(),
+      "--plaintext",
+      "-A",
+      "other.toml",
+    ];
+    let loaded = options(&args).load().unwrap();
+    assert_eq!(loaded.accounts, PathBuf::from("other.toml"));
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn reports_all_problems() {
+    let _env = ENV.lock().unwrap();
+    let args = [
+      "--plaintext",
+      "-b",
+      "0.0.0.0:1",
+      "--websocket",
+      "0.0.0.0:1",
+      "--max-packet-size",
+      "0",
+      "--heartbeat-interval",
+      "0",
+      "--icon",
+      "/nonexistent/icon.png",
+    ];
+    let problems = problems(options(&args).load());
+    let expected = [
+      "server: 0.0.0.0:1 is used by multiple listeners",
+      "tls.plaintext (--plaintext): refusing",
+      "status.icon (--icon): can't access /nonexistent/icon.png",
+      "limits.max_packet_size (--max-packet-size)",
+      "timeouts.heartbeat_interval (--heartbeat-interval)",
+    ];
+    for expected in &expected {
+      assert!(
+        problems.iter().any(|p| p.starts_with(expected)),
+        "missing {:?} in {:#?}",
+        expected,
+        problems
+      );
+    }
+
+    let report = ConfigError(vec!["a".into(), "b".into()]).to_string();
+    assert_eq!(report, "Invalid configuration:\n  - a\n  - b");
+  }
+
+  #[test]
+  fn requires_certificate() {
+    let _env = ENV.lock().unwrap();
+    let problems = problems(options(&[]).load());
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].starts_with("tls (--tls-cert, --tls-key)"));
+  }
+
+  #[test]
+  fn rejects_unknown_settings() {
+    let dir = directory("unknown", &[("server.toml", "[tls]\nplaintxt = true\n")]);
+    let problems = problems(options(&["-c", dir.join("server.toml").to_str().unwrap()]).load());
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].contains("unknown field `plaintxt`"));
+    fs::remove_dir_all(dir).unwrap();
   }
 }
@@ -2,9 +2,12 @@ use tokio::sync::{mpsc, oneshot};
 use tokio::task::JoinHandle;
 
 use std::collections::HashSet;
+use std::net::SocketAddr;
 use std::sync::Arc;
 
-use crate::game::net::NetSenderHandle;
+use crate::game::net::packet::{GamePacket, LeaveReason, OutgoingPacket};
+use crate::game::net::{self, NetSenderHandle};
+use crate::game::permission_level::PermissionLevel;
 
 // Structures
 
@@ -20,10 +23,22 @@ pub struct GameActor {
     pub players: HashSet<NetSenderHandle>,
 }
 
+/// Identity of the connection that sent an in-game packet
+#[derive(Clone, Debug)]
+pub struct Player {
+    pub sender: NetSenderHandle,
+    pub username: Option<String>,
+    pub permission_level: PermissionLevel,
+}
+
 #[derive(Debug)]
 enum GameMessage {
     StopActor,
     GetPlayerCount(oneshot::Sender<usize>),
+    SetInfo(Arc<GameInfo>),
+    AddPlayer(NetSenderHandle),
+    RemovePlayer(SocketAddr, LeaveReason, oneshot::Sender<()>),
+    Packet(Player, GamePacket),
 }
 
 #[derive(Debug)]
@@ -34,7 +49,7 @@ pub struct GameInfo {
 
 // Implementations
 
-const ACTOR_DROPPED_ERROR: &'static str = "GameActor was dropped, oopsie!";
+const ACTOR_DROPPED_ERROR: &str = "GameActor was dropped, oopsie!";
 
 impl GameHandle {
     pub async fn stop_actor(&mut self) {
@@ -51,6 +66,36 @@ impl GameHandle {
             .expect(ACTOR_DROPPED_ERROR);
         recv.await.expect(ACTOR_DROPPED_ERROR)
     }
+    /// Adds a player to this game and confirms the join to the player
+    pub async fn add_player(&mut self, player: NetSenderHandle) {
+        self.sender
+            .send(GameMessage::AddPlayer(player))
+            .await
+            .expect(ACTOR_DROPPED_ERROR)
+    }
+    /// Removes a player from this game. Once this returns, the game
+    /// won't send any more packets to that player.
+    pub async fn remove_player(&mut self, address: SocketAddr, reason: LeaveReason) {
+        let (send, recv) = oneshot::channel();
+        self.sender
+            .send(GameMessage::RemovePlayer(address, reason, send))
+            .await
+            .expect(ACTOR_DROPPED_ERROR);
+        recv.await.expect(ACTOR_DROPPED_ERROR)
+    }
+    /// Forwards a packet to the game, which handles it on its own task
+    pub async fn send_packet(&mut self, player: Player, packet: GamePacket) {
+        // The game might have been deleted in the meantime
+        drop(self.sender.send(GameMessage::Packet(player, packet)).await);
+    }
+    /// Replaces the info of this game, both in this handle and the actor
+    pub async fn set_info(&mut self, info: GameInfo) {
+        self.info = Arc::new(info);
+        self.sender
+            .send(GameMessage::SetInfo(self.info.clone()))
+            .await
+            .expect(ACTOR_DROPPED_ERROR)
+    }
 }
 
 impl GameActor {
@@ -71,16 +116,76 @@ impl GameActor {
             tokio::spawn(async move { self.actor(recv).await }),
         )
     }
-    async fn actor(self, mut recv: mpsc::Receiver<GameMessage>) -> Self {
+    async fn actor(mut self, mut recv: mpsc::Receiver<GameMessage>) -> Self {
         loop {
             match recv.recv().await {
                 None => return self,
                 Some(GameMessage::StopActor) => return self,
                 Some(GameMessage::GetPlayerCount(cb)) => {
-                    drop(cb.send(self.players.len()));
+                    let _ = cb.send(self.players.len());
+                    continue;
+                }
+                Some(GameMessage::SetInfo(info)) => {
+                    self.info = info;
+                    continue;
+                }
+                Some(GameMessage::AddPlayer(player)) => {
+                    use crate::game::net::packet::{GameActionStatus, JoinGameResponsePacket};
+                    // This is the first packet the player receives from this game
+                    player.send_packet(JoinGameResponsePacket {
+                        status: GameActionStatus::Success,
+                        id: self.info.id,
+                    });
+                    self.players.insert(player);
+                    continue;
+                }
+                Some(GameMessage::RemovePlayer(address, reason, cb)) => {
+                    use crate::game::net::packet::LeftGamePacket;
+                    let player = self
+                        .players
+                        .iter()
+                        .find(|p| p.address() == address)
+                        .cloned();
+                    if let Some(player) = player {
+                        self.players.remove(&player);
+                        // This is the last packet the player receives from this game
+                        player.send_packet(LeftGamePacket { reason });
+                    }
+                    let _ = cb.send(());
+                    continue;
+                }
+                Some(GameMessage::Packet(player, packet)) => {
+                    // Packets may still arrive after a player left
+                    if self.players.contains(&player.sender) {
+                        self.process_packet(player, packet).await;
+                    }
                     continue;
                 }
             }
         }
     }
+    async fn process_packet(&mut self, player: Player, packet: GamePacket) {
+        use crate::game::net::packet::{ChatMessagePacket, GameInfoPacket};
+        match packet {
+            GamePacket::RequestGameInfo(_) => {
+                let response = GameInfoPacket {
+                    id: self.info.id,
+                    name: self.info.name.clone(),
+                    players: self.players.len() as u32,
+                };
+                player.sender.send_packet(response);
+            }
+            GamePacket::Chat(chat) => {
+                let message = ChatMessagePacket {
+                    sender: player.username.unwrap_or_else(|| "Guest".into()),
+                    message: chat.message,
+                };
+                self.broadcast(message);
+            }
+        }
+    }
+    /// Sends a packet to every player of this game
+    fn broadcast<P: OutgoingPacket>(&self, packet: P) {
+        net::broadcast(&self.players, packet);
+    }
 }
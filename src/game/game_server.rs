@@ -1,43 +1,71 @@
 use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
-use tokio::net::{TcpListener, TcpStream};
+use futures::future::{self, AbortHandle};
+use tokio::net::TcpStream;
 use tokio::sync::{mpsc, oneshot, Mutex};
 use tokio::task::JoinHandle;
 
-use tokio_rustls::{
-    rustls::{Certificate, PrivateKey, ServerConfig},
-    TlsAcceptor,
-};
-
 use super::*;
+use net::packet::{
+    DisconnectReason, LeaveReason, ListGamesEntry, ListGamesPacket, PingStatusPacket,
+};
+use status::ServerStatus;
 
 // Structures
 
 #[derive(Clone, Debug)]
 pub struct GameServerHandle {
-    address: SocketAddr,
     sender: mpsc::Sender<GameServerMessage>,
 }
 
 pub struct GameServerActor {
-    address: SocketAddr,
-    tls_acceptor: TlsAcceptor,
+    /// Listeners of TCP and WebSocket connections, taken when the actor starts
+    listeners: Vec<net::Listener>,
+    transport: net::Transport,
+    /// Stops the task reloading the TLS certificate
+    watcher: Option<AbortHandle>,
+    accounts: accounts::AccountStoreHandle,
+    /// Status JSON sent to pinging connections
+    status: String,
+    limits: Arc<net::PacketLimits>,
+    heartbeat: net::HeartbeatConfig,
     /// Shared mutable HashMap containing all active connections.
     connections: Arc<Mutex<HashMap<SocketAddr, net::NetManagerHandle>>>,
+    /// Number of accepted connections that are not closed yet
+    open_connections: Arc<AtomicUsize>,
     games: HashMap<u64, GameHandle>,
+    next_game_id: u64,
+    /// Connections in the `Login` state, which receive all game list changes
+    lobby: HashMap<SocketAddr, net::NetSenderHandle>,
+    /// Game id of every connection that is playing a game
+    players: HashMap<SocketAddr, u64>,
 }
 
 #[derive(Debug)]
 enum GameServerMessage {
     StopActor,
     GetGames(oneshot::Sender<Vec<(u64, GameHandle)>>),
+    GetStatus(oneshot::Sender<PingStatusPacket>),
+    CreateGame(String, oneshot::Sender<u64>),
+    DeleteGame(u64, oneshot::Sender<bool>),
+    RenameGame(u64, String, oneshot::Sender<bool>),
+    EnterLobby(net::NetSenderHandle),
+    SyncGames(SocketAddr),
+    JoinGame(
+        u64,
+        net::NetSenderHandle,
+        oneshot::Sender<Option<GameHandle>>,
+    ),
+    LeaveGame(net::NetSenderHandle),
+    ConnectionClosed(SocketAddr),
 }
 
 // Implementations
 
-const ACTOR_DROPPED_MESSAGE: &'static str = "GameServerActor was dropped, oopsie!";
+const ACTOR_DROPPED_MESSAGE: &str = "GameServerActor was dropped, oopsie!";
 
 impl GameServerHandle {
     pub async fn stop_actor(&mut self) {
@@ -54,38 +82,130 @@ impl GameServerHandle {
             .expect(ACTOR_DROPPED_MESSAGE);
         recv.await.expect(ACTOR_DROPPED_MESSAGE)
     }
+    /// Returns the current statistics of this server
+    pub async fn get_status(&mut self) -> PingStatusPacket {
+        let (send, recv) = oneshot::channel();
+        self.sender
+            .send(GameServerMessage::GetStatus(send))
+            .await
+            .expect(ACTOR_DROPPED_MESSAGE);
+        recv.await.expect(ACTOR_DROPPED_MESSAGE)
+    }
+    /// Creates and starts a new game and returns its id
+    pub async fn create_game(&mut self, name: String) -> u64 {
+        let (send, recv) = oneshot::channel();
+        self.sender
+            .send(GameServerMessage::CreateGame(name, send))
+            .await
+            .expect(ACTOR_DROPPED_MESSAGE);
+        recv.await.expect(ACTOR_DROPPED_MESSAGE)
+    }
+    /// Stops and removes a game and returns if it existed
+    pub async fn delete_game(&mut self, id: u64) -> bool {
+        let (send, recv) = oneshot::channel();
+        self.sender
+            .send(GameServerMessage::DeleteGame(id, send))
+            .await
+            .expect(ACTOR_DROPPED_MESSAGE);
+        recv.await.expect(ACTOR_DROPPED_MESSAGE)
+    }
+    /// Renames a game and returns if it exists
+    pub async fn rename_game(&mut self, id: u64, name: String) -> bool {
+        let (send, recv) = oneshot::channel();
+        self.sender
+            .send(GameServerMessage::RenameGame(id, name, send))
+            .await
+            .expect(ACTOR_DROPPED_MESSAGE);
+        recv.await.expect(ACTOR_DROPPED_MESSAGE)
+    }
+    /// Subscribes a connection to game list changes and
+    /// sends it the full list of games
+    pub async fn enter_lobby(&mut self, sender: net::NetSenderHandle) {
+        self.sender
+            .send(GameServerMessage::EnterLobby(sender))
+            .await
+            .expect(ACTOR_DROPPED_MESSAGE)
+    }
+    /// Re-sends the full list of games to a connection in the lobby
+    pub async fn sync_games(&mut self, address: SocketAddr) {
+        self.sender
+            .send(GameServerMessage::SyncGames(address))
+            .await
+            .expect(ACTOR_DROPPED_MESSAGE)
+    }
+    /// Moves a connection from the lobby into a game and returns
+    /// the game, or `None` if it doesn't exist
+    pub async fn join_game(&mut self, id: u64, sender: net::NetSenderHandle) -> Option<GameHandle> {
+        let (send, recv) = oneshot::channel();
+        self.sender
+            .send(GameServerMessage::JoinGame(id, sender, send))
+            .await
+            .expect(ACTOR_DROPPED_MESSAGE);
+        recv.await.expect(ACTOR_DROPPED_MESSAGE)
+    }
+    /// Moves a connection from its game back into the lobby
+    pub async fn leave_game(&mut self, sender: net::NetSenderHandle) {
+        self.sender
+            .send(GameServerMessage::LeaveGame(sender))
+            .await
+            .expect(ACTOR_DROPPED_MESSAGE)
+    }
+    /// Removes every trace of a closed connection
+    pub async fn connection_closed(&mut self, address: SocketAddr) {
+        // Connections are also closed while the server is shutting down,
+        // in which case the actor might already be gone
+        drop(
+            self.sender
+                .send(GameServerMessage::ConnectionClosed(address))
+                .await,
+        );
+    }
 }
 
 impl std::fmt::Debug for GameServerActor {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("GameServerActor")
-            .field("address", &self.address)
+            .field("listeners", &self.listeners)
             .field("connections", &self.connections)
             .field("games", &self.games)
-            .field("tls_acceptor", &"<...>")
+            .field("accounts", &self.accounts)
+            .field("status", &self.status)
+            .field("limits", &self.limits)
+            .field("heartbeat", &self.heartbeat)
+            .field("transport", &self.transport)
             .finish()
     }
 }
 
 impl GameServerActor {
-    pub fn new<A: Into<SocketAddr>>(addr: A, encryption: (Vec<Certificate>, PrivateKey)) -> Self {
-        let mut tls_config = ServerConfig::new(tokio_rustls::rustls::NoClientAuth::new().into());
-        tls_config
-            .set_single_cert(encryption.0, encryption.1)
-            .unwrap();
+    pub fn new(
+        listeners: Vec<net::Listener>,
+        transport: net::Transport,
+        accounts: accounts::AccountStoreHandle,
+        status: ServerStatus,
+        limits: net::PacketLimits,
+        heartbeat: net::HeartbeatConfig,
+    ) -> Self {
         Self {
-            address: addr.into(),
-            tls_acceptor: TlsAcceptor::from(Arc::from(tls_config)),
+            listeners,
+            transport,
+            watcher: None,
+            accounts,
+            status: status.to_json(),
+            limits: Arc::new(limits),
+            heartbeat,
             games: HashMap::new(),
+            // Id 0 is never used, so it can't be mistaken for a valid game
+            next_game_id: 1,
+            lobby: HashMap::new(),
+            players: HashMap::new(),
             connections: Mutex::new(HashMap::new()).into(),
+            open_connections: Arc::new(AtomicUsize::new(0)),
         }
     }
     pub fn spawn(self) -> (GameServerHandle, JoinHandle<GameServerActor>) {
         let (send, recv) = mpsc::channel(1024);
-        let handle = GameServerHandle {
-            sender: send,
-            address: self.address,
-        };
+        let handle = GameServerHandle { sender: send };
 
         (
             handle.clone(),
@@ -97,31 +217,46 @@ impl GameServerActor {
         mut recv: mpsc::Receiver<GameServerMessage>,
         game_server_handle: GameServerHandle,
     ) -> Self {
-        use futures::FutureExt;
+        use futures::{FutureExt, StreamExt};
 
-        let mut net_listener = TcpListener::bind(self.address)
-            .await
-            .expect("Failed to register server");
-        println!("(ℹ) Server listening on {}", self.address);
+        let listeners = std::mem::take(&mut self.listeners);
+        for listener in &listeners {
+            if listener.websocket {
+                println!("(ℹ) WebSocket server listening on {}", listener.address);
+            } else {
+                println!("(ℹ) Server listening on {}", listener.address);
+            }
+        }
+        let mut incoming = futures::stream::select_all(listeners.into_iter().map(|l| l.incoming()));
+        match &self.transport {
+            net::Transport::Plaintext => {
+                println!("(⚠) Connections are not encrypted, only use this for development!");
+            }
+            net::Transport::Tls(certificate) => {
+                let (watch, watcher) = future::abortable(certificate.clone().watch());
+                tokio::spawn(watch);
+                self.watcher = Some(watcher);
+            }
+        }
 
         loop {
             futures::select! {
                 msg = recv.recv().fuse() => {
                     match msg {
                         None => return self.stop_net().await,
-                        Some(msg) => if !self.process_msg(msg) {
+                        Some(msg) => if !self.process_msg(msg).await {
                             return self.stop_net().await
                         },
                     }
                 },
-                con_res = net_listener.accept().fuse() => {
-                    match con_res {
+                con_res = incoming.next() => {
+                    match con_res.expect("Listeners never stop accepting") {
                         Err(e) => {
                             println!("Failed to accept connection");
                             continue;
                         }
-                        Ok((stream, address)) => {
-                            self.accept_net(stream, address, game_server_handle.clone());
+                        Ok((stream, address, websocket)) => {
+                            self.accept_net(stream, address, websocket, game_server_handle.clone());
                             continue;
                         }
                     }
@@ -129,8 +264,12 @@ impl GameServerActor {
             }
         }
     }
-    /// Stops this actor after closing all network connections
-    async fn stop_net(self) -> Self {
+    /// Stops this actor after closing all network connections and games
+    async fn stop_net(mut self) -> Self {
+        if let Some(watcher) = self.watcher.take() {
+            watcher.abort();
+        }
+
         let mut cons_lock = self.connections.lock().await;
         let cons_len = cons_lock.len();
 
@@ -145,7 +284,12 @@ impl GameServerActor {
         let mut tasks = Vec::with_capacity(cons_len);
         for (_, mut connection) in cons {
             tasks.push(tokio::task::spawn(async move {
-                connection.stop_actor().await;
+                connection
+                    .disconnect(
+                        DisconnectReason::ServerShutdown,
+                        "The server is shutting down".into(),
+                    )
+                    .await;
             }));
         }
 
@@ -156,6 +300,10 @@ impl GameServerActor {
             drop(jh.await);
         }
 
+        for (_, mut game) in self.games.drain() {
+            game.stop_actor().await;
+        }
+
         self
     }
     /// Accepts a network connection socket
@@ -163,14 +311,45 @@ impl GameServerActor {
         &mut self,
         stream: TcpStream,
         addr: SocketAddr,
+        websocket: bool,
         game_server_handle: GameServerHandle,
     ) {
         println!("(ℹ) [+] Connection from {}", addr);
-        let actor =
-            net::NetManagerActor::new(addr, stream, self.tls_acceptor.clone(), game_server_handle);
-        let (handle, jh) = actor.spawn();
+        let full = self.open_connections.load(Ordering::SeqCst) >= self.limits.max_connections;
+        let actor = net::NetManagerActor::new(
+            addr,
+            stream,
+            net::Acceptor {
+                transport: self.transport.clone(),
+                websocket,
+            },
+            game_server_handle.clone(),
+            self.accounts.clone(),
+            self.limits.clone(),
+            self.heartbeat,
+        );
+        let (mut handle, jh) = actor.spawn();
+
+        if full {
+            // The client still learns why it can't connect
+            let message = format!(
+                "The server is full, it accepts at most {} connections",
+                self.limits.max_connections
+            );
+            tokio::task::spawn(async move {
+                handle
+                    .disconnect(DisconnectReason::ServerFull, message)
+                    .await;
+                drop(jh.await);
+                println!("(ℹ) [-] {address} disconnected", address = addr);
+            });
+            return;
+        }
 
         let cons_mutex = self.connections.clone();
+        let open_connections = self.open_connections.clone();
+        open_connections.fetch_add(1, Ordering::SeqCst);
+        let mut server = game_server_handle;
 
         // Insert handle and wait until disconnect to remove it
         // We're going to wait a lot here, so green threads (tasks)
@@ -192,10 +371,13 @@ impl GameServerActor {
             let mut lock = cons_mutex.lock().await;
             lock.remove(&addr);
             drop(lock);
+            open_connections.fetch_sub(1, Ordering::SeqCst);
+
+            server.connection_closed(addr).await;
         });
     }
     /// Processes an actor message and returns if the actor should continue listening
-    fn process_msg(&mut self, msg: GameServerMessage) -> bool {
+    async fn process_msg(&mut self, msg: GameServerMessage) -> bool {
         match msg {
             GameServerMessage::StopActor => false,
             GameServerMessage::GetGames(cb) => {
@@ -209,6 +391,167 @@ impl GameServerActor {
                 );
                 true
             }
+            GameServerMessage::GetStatus(cb) => {
+                let status = PingStatusPacket {
+                    players: (self.lobby.len() + self.players.len()) as u32,
+                    games: self.games.len() as u32,
+                    status: self.status.clone(),
+                };
+                drop(cb.send(status));
+                true
+            }
+            GameServerMessage::CreateGame(name, cb) => {
+                let id = self.next_game_id;
+                self.next_game_id += 1;
+
+                let (handle, _) = GameActor::new(id, name).spawn();
+                println!("(ℹ) Created game {} ({:?})", id, handle.info.name);
+                self.games.insert(id, handle);
+                let _ = cb.send(id);
+                self.broadcast_game(id).await;
+                true
+            }
+            GameServerMessage::DeleteGame(id, cb) => {
+                let deleted = match self.games.remove(&id) {
+                    Some(mut game) => {
+                        self.kick_players(&mut game).await;
+                        game.stop_actor().await;
+                        println!("(ℹ) Deleted game {} ({:?})", id, game.info.name);
+                        true
+                    }
+                    None => false,
+                };
+                let _ = cb.send(deleted);
+                if deleted {
+                    self.broadcast_lobby(vec![ListGamesEntry::Remove { id }]);
+                }
+                true
+            }
+            GameServerMessage::RenameGame(id, name, cb) => {
+                let renamed = match self.games.get_mut(&id) {
+                    Some(game) => {
+                        println!(
+                            "(ℹ) Renamed game {} ({:?} -> {:?})",
+                            id, game.info.name, name
+                        );
+                        game.set_info(GameInfo { id, name }).await;
+                        true
+                    }
+                    None => false,
+                };
+                let _ = cb.send(renamed);
+                if renamed {
+                    self.broadcast_game(id).await;
+                }
+                true
+            }
+            GameServerMessage::EnterLobby(sender) => {
+                self.enter_lobby(sender).await;
+                true
+            }
+            GameServerMessage::SyncGames(address) => {
+                let entries = self.list_games().await;
+                if let Some(sender) = self.lobby.get(&address) {
+                    sender.send_packet(ListGamesPacket { entries });
+                }
+                true
+            }
+            GameServerMessage::JoinGame(id, sender, cb) => {
+                let address = sender.address();
+                let joined = match self.games.get_mut(&id) {
+                    Some(game) => {
+                        self.lobby.remove(&address);
+                        game.add_player(sender).await;
+                        self.players.insert(address, id);
+                        Some(game.clone())
+                    }
+                    None => None,
+                };
+                let is_joined = joined.is_some();
+                drop(cb.send(joined));
+                if is_joined {
+                    self.broadcast_game(id).await;
+                }
+                true
+            }
+            GameServerMessage::LeaveGame(sender) => {
+                self.remove_player(sender.address(), LeaveReason::Left)
+                    .await;
+                self.enter_lobby(sender).await;
+                true
+            }
+            GameServerMessage::ConnectionClosed(address) => {
+                self.lobby.remove(&address);
+                self.remove_player(address, LeaveReason::Left).await;
+                true
+            }
+        }
+    }
+    /// Sends the full list of games to a connection and subscribes it to changes
+    async fn enter_lobby(&mut self, sender: net::NetSenderHandle) {
+        let response = ListGamesPacket {
+            entries: self.list_games().await,
+        };
+        sender.send_packet(response);
+        self.lobby.insert(sender.address(), sender);
+    }
+    /// Removes a connection from the game it is playing, if any
+    async fn remove_player(&mut self, address: SocketAddr, reason: LeaveReason) {
+        if let Some(id) = self.players.remove(&address) {
+            if let Some(game) = self.games.get_mut(&id) {
+                game.remove_player(address, reason).await;
+            }
+            self.broadcast_game(id).await;
+        }
+    }
+    /// Removes all players from a game and sends them back to the lobby
+    async fn kick_players(&mut self, game: &mut GameHandle) {
+        let id = game.info.id;
+        let addresses: Vec<SocketAddr> = self
+            .players
+            .iter()
+            .filter(|(_, game_id)| **game_id == id)
+            .map(|(address, _)| *address)
+            .collect();
+
+        for address in addresses {
+            self.players.remove(&address);
+            game.remove_player(address, LeaveReason::GameDeleted).await;
+
+            let connection = self.connections.lock().await.get(&address).cloned();
+            if let Some(mut connection) = connection {
+                connection.leave_game().await;
+            }
+        }
+    }
+    /// Builds a full game list containing an `Add` entry for every game
+    async fn list_games(&mut self) -> Vec<ListGamesEntry> {
+        let mut entries = Vec::with_capacity(self.games.len());
+        for (id, game) in self.games.iter_mut() {
+            entries.push(ListGamesEntry::Add {
+                id: *id,
+                name: game.info.name.clone(),
+                players: game.get_player_count().await as u32,
+            });
+        }
+        entries
+    }
+    /// Sends the current entry of a game to every connection in the lobby
+    async fn broadcast_game(&mut self, id: u64) {
+        if let Some(game) = self.games.get_mut(&id) {
+            let entry = ListGamesEntry::Add {
+                id,
+                name: game.info.name.clone(),
+                players: game.get_player_count().await as u32,
+            };
+            self.broadcast_lobby(vec![entry]);
+        }
+    }
+    /// Sends game list changes to every connection in the lobby
+    fn broadcast_lobby(&self, entries: Vec<ListGamesEntry>) {
+        let changes = net::GameChanges::new(entries);
+        for sender in self.lobby.values() {
+            sender.send_game_changes(&changes);
         }
     }
 }
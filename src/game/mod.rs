@@ -4,6 +4,8 @@ mod game_server;
 pub use game_instance::*;
 pub use game_server::*;
 
+pub mod accounts;
 pub mod config;
 pub mod net;
-pub mod permission_level;
+pub use haendler_protocol::permission_level;
+pub mod status;
@@ -0,0 +1,145 @@
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::time::{Duration, Instant};
+
+use super::packet::HeartbeatPacket;
+
+/// Settings of the checks whether a connection is still alive
+#[derive(Clone, Copy, Debug)]
+pub struct HeartbeatConfig {
+  /// Time between two heartbeats
+  pub interval: Duration,
+  /// Number of unanswered heartbeats after which a connection is closed
+  pub max_missed: usize,
+  /// Time a connection may take to complete the handshake
+  pub handshake_timeout: Duration,
+}
+
+/// Heartbeats of a single connection and its round-trip time
+#[derive(Debug, Default)]
+pub struct Heartbeats {
+  next_id: u32,
+  /// Sent heartbeats that were not echoed yet
+  pending: VecDeque<(u32, Instant)>,
+  rtt: Option<Duration>,
+}
+
+// Implementations
+
+impl Heartbeats {
+  /// Creates the next heartbeat and starts waiting for its echo
+  pub fn send(&mut self) -> HeartbeatPacket {
+    let id = self.next_id;
+    self.next_id = self.next_id.wrapping_add(1);
+    self.pending.push_back((id, Instant::now()));
+    let rtt = self
+      .rtt
+      .map_or(0, |rtt| rtt.as_micros().try_into().unwrap_or(u32::MAX));
+    HeartbeatPacket { id, rtt }
+  }
+  /// Number of heartbeats that were not echoed yet
+  pub fn missed(&self) -> usize {
+    self.pending.len()
+  }
+  /// Smoothed round-trip time
+  pub fn rtt(&self) -> Option<Duration> {
+    self.rtt
+  }
+  /// Handles the echo of a heartbeat. Older heartbeats are no longer
+  /// awaited, since the connection is evidently alive.
+  pub fn echo(&mut self, id: u32) {
+    let position = match self.pending.iter().position(|(pending, _)| *pending == id) {
+      Some(position) => position,
+      // Unknown or duplicate echo
+      None => return,
+    };
+    let (_, sent) = self.pending.drain(..=position).next_back().unwrap();
+    let sample = sent.elapsed();
+    // Same smoothing as the TCP round-trip time estimator
+    self.rtt = Some(match self.rtt {
+      Some(rtt) => (rtt * 7 + sample) / 8,
+      None => sample,
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn echo_in_order() {
+    let mut heartbeats = Heartbeats::default();
+    let first = heartbeats.send();
+    let second = heartbeats.send();
+    assert_eq!((first.id, second.id), (0, 1));
+    assert_eq!(first.rtt, 0);
+    assert_eq!(heartbeats.missed(), 2);
+
+    heartbeats.echo(first.id);
+    assert_eq!(heartbeats.missed(), 1);
+    assert!(heartbeats.rtt().is_some());
+    heartbeats.echo(second.id);
+    assert_eq!(heartbeats.missed(), 0);
+  }
+
+  #[test]
+  fn echo_out_of_order_drops_older() {
+    let mut heartbeats = Heartbeats::default();
+    let ids: Vec<_> = (0..4).map(|_| heartbeats.send().id).collect();
+    heartbeats.echo(ids[2]);
+    // Only the heartbeat sent after the echoed one is still awaited
+    assert_eq!(heartbeats.missed(), 1);
+    // Echoes of the dropped heartbeats are ignored
+    heartbeats.echo(ids[0]);
+    heartbeats.echo(ids[1]);
+    assert_eq!(heartbeats.missed(), 1);
+    heartbeats.echo(ids[3]);
+    assert_eq!(heartbeats.missed(), 0);
+  }
+
+  #[test]
+  fn unknown_and_duplicate_echoes() {
+    let mut heartbeats = Heartbeats::default();
+    assert_eq!(heartbeats.send().id, 0);
+    heartbeats.echo(42);
+    assert_eq!(heartbeats.missed(), 1);
+    assert!(heartbeats.rtt().is_none());
+
+    heartbeats.echo(0);
+    let rtt = heartbeats.rtt();
+    heartbeats.echo(0);
+    assert_eq!(heartbeats.missed(), 0);
+    assert_eq!(heartbeats.rtt(), rtt);
+  }
+
+  #[test]
+  fn missed_counts_unanswered() {
+    let mut heartbeats = Heartbeats::default();
+    for missed in 1..=5 {
+      heartbeats.send();
+      assert_eq!(heartbeats.missed(), missed);
+    }
+  }
+
+  #[test]
+  fn ids_wrap_around() {
+    let mut heartbeats = Heartbeats {
+      next_id: u32::MAX,
+      ..Heartbeats::default()
+    };
+    assert_eq!(heartbeats.send().id, u32::MAX);
+    assert_eq!(heartbeats.send().id, 0);
+    heartbeats.echo(0);
+    assert_eq!(heartbeats.missed(), 0);
+  }
+
+  #[test]
+  fn sends_rtt() {
+    let mut heartbeats = Heartbeats {
+      rtt: Some(Duration::from_millis(20)),
+      ..Heartbeats::default()
+    };
+    assert_eq!(heartbeats.send().rtt, 20_000);
+  }
+}
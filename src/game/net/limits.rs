@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::str::FromStr;
+
+use haendler_protocol::frame::Header;
+
+use super::State;
+
+/// Maximum body sizes of server bound packets. The most specific
+/// limit applies: packet, then state, then the default.
+#[derive(Clone, Debug)]
+pub struct PacketLimits {
+  default: u32,
+  states: HashMap<State, u32>,
+  packets: HashMap<(State, u16), u32>,
+  /// Maximum number of bytes of client bound packets queued for a connection
+  pub send_queue: usize,
+  /// Maximum number of open connections, further ones are refused
+  pub max_connections: usize,
+}
+
+/// Limit of a single state or packet, parsed from `state=bytes`
+/// or `state:id=bytes`
+#[derive(Clone, Copy, Debug)]
+pub struct PacketLimit {
+  state: State,
+  packet_id: Option<u16>,
+  max_body_size: u32,
+}
+
+// Implementations
+
+impl PacketLimits {
+  pub fn new(default: u32) -> Self {
+    let mut states = HashMap::new();
+    // Packets in these states are tiny, anything bigger is malicious
+    states.insert(State::Handshake, 64);
+    states.insert(State::Ping, 64);
+    Self {
+      default,
+      states,
+      packets: HashMap::new(),
+      send_queue: 1024 * 1024,
+      max_connections: 1000,
+    }
+  }
+  pub fn insert(&mut self, limit: PacketLimit) {
+    match limit.packet_id {
+      Some(id) => self.packets.insert((limit.state, id), limit.max_body_size),
+      None => self.states.insert(limit.state, limit.max_body_size),
+    };
+  }
+  /// Returns the largest body size any packet may have
+  pub fn largest(&self) -> u32 {
+    let states = self.states.values();
+    let packets = self.packets.values();
+    states.chain(packets).copied().fold(self.default, u32::max)
+  }
+  /// Returns the maximum body size of a packet received in the given state
+  pub fn max_body_size(&self, state: State, packet_id: u16) -> u32 {
+    self
+      .packets
+      .get(&(state, packet_id))
+      .or_else(|| self.states.get(&state))
+      .copied()
+      .unwrap_or(self.default)
+  }
+  /// Returns the body length of a received packet, or the limit it exceeds
+  pub fn body_len(&self, state: State, header: Header) -> Result<usize, u32> {
+    let max_body_size = self.max_body_size(state, header.packet_id);
+    if header.body_len > max_body_size {
+      return Err(max_body_size);
+    }
+    header.body_len.try_into().map_err(|_| max_body_size)
+  }
+}
+
+impl FromStr for PacketLimit {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let (target, size) = s.split_once('=').ok_or("expected STATE[:ID]=BYTES")?;
+    let (state, packet_id) = match target.split_once(':') {
+      Some((state, id)) => (state, Some(parse_int(id)?)),
+      None => (target, None),
+    };
+    let state = match state.to_lowercase().as_str() {
+      "handshake" => State::Handshake,
+      "ping" => State::Ping,
+      "login" => State::Login,
+      "game" => State::Game,
+      _ => return Err(format!("unknown state {:?}", state)),
+    };
+    Ok(Self {
+      state,
+      packet_id,
+      max_body_size: parse_int(size)?,
+    })
+  }
+}
+
+/// Parses a decimal or `0x` prefixed hexadecimal number
+fn parse_int<T: FromStr + num_traits::Num>(s: &str) -> Result<T, String> {
+  let s = s.trim();
+  let hex = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X"));
+  let result = if let Some(hex) = hex {
+    T::from_str_radix(hex, 16).ok()
+  } else {
+    s.parse().ok()
+  };
+  result.ok_or_else(|| format!("invalid number {:?}", s))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn header(packet_id: u16, body_len: u32) -> Header {
+    Header {
+      packet_id,
+      body_len,
+    }
+  }
+
+  #[test]
+  fn most_specific_limit_applies() {
+    let mut limits = PacketLimits::new(1000);
+    limits.insert("login=500".parse().unwrap());
+    limits.insert("login:0x2=2000".parse().unwrap());
+    assert_eq!(limits.max_body_size(State::Game, 2), 1000);
+    assert_eq!(limits.max_body_size(State::Login, 1), 500);
+    assert_eq!(limits.max_body_size(State::Login, 2), 2000);
+    assert_eq!(limits.max_body_size(State::Handshake, 0), 64);
+    assert_eq!(limits.largest(), 2000);
+  }
+
+  #[test]
+  fn rejects_oversized_header() {
+    let limits = PacketLimits::new(1000);
+    assert_eq!(limits.body_len(State::Game, header(2, 1000)), Ok(1000));
+    assert_eq!(limits.body_len(State::Game, header(2, 1001)), Err(1000));
+    assert_eq!(limits.body_len(State::Game, header(2, u32::MAX)), Err(1000));
+    assert_eq!(limits.body_len(State::Handshake, header(0, 65)), Err(64));
+  }
+
+  #[test]
+  fn parses_limits() {
+    assert!("game:2=512".parse::<PacketLimit>().is_ok());
+    assert!("GAME=0x200".parse::<PacketLimit>().is_ok());
+    assert!("lobby=512".parse::<PacketLimit>().is_err());
+    assert!("game:x=512".parse::<PacketLimit>().is_err());
+    assert!("game".parse::<PacketLimit>().is_err());
+  }
+}
@@ -0,0 +1,60 @@
+use std::io;
+use std::net::SocketAddr;
+
+use futures::{Stream, StreamExt};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::{TcpListener, TcpStream};
+
+// Structures
+
+/// Bound address the server accepts connections on
+#[derive(Debug)]
+pub struct Listener {
+  pub address: SocketAddr,
+  /// Whether connections send packets as WebSocket messages
+  pub websocket: bool,
+  listener: TcpListener,
+}
+
+// Implementations
+
+impl Listener {
+  /// Binds an address. IPv6 addresses only accept IPv6 connections, so
+  /// an IPv4 and an IPv6 address may use the same port.
+  pub fn bind(address: SocketAddr, websocket: bool) -> io::Result<Self> {
+    let bind = || -> io::Result<TcpListener> {
+      let socket = Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+      )?;
+      if address.is_ipv6() {
+        socket.set_only_v6(true)?;
+      }
+      // Like the standard library, allow restarting the server right away
+      #[cfg(unix)]
+      socket.set_reuse_address(true)?;
+      socket.bind(&address.into())?;
+      socket.listen(1024)?;
+      socket.set_nonblocking(true)?;
+      TcpListener::from_std(socket.into())
+    };
+    let listener = bind()
+      .map_err(|e| io::Error::new(e.kind(), format!("failed to listen on {}: {}", address, e)))?;
+    Ok(Self {
+      address,
+      websocket,
+      listener,
+    })
+  }
+  /// Accepted connections, their addresses and whether they are
+  /// WebSocket connections
+  pub fn incoming(self) -> impl Stream<Item = io::Result<(TcpStream, SocketAddr, bool)>> + Unpin {
+    let websocket = self.websocket;
+    self.listener.map(move |stream| {
+      let stream = stream?;
+      let address = stream.peer_addr()?;
+      Ok((stream, address, websocket))
+    })
+  }
+}
@@ -0,0 +1,13 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether the contents of all packets are printed
+static LOG_PACKETS: AtomicBool = AtomicBool::new(false);
+
+/// Prints the contents of all packets sent and received. Only for debugging!
+pub fn set_log_packets(enabled: bool) {
+  LOG_PACKETS.store(enabled, Ordering::Relaxed);
+}
+
+pub(super) fn log_packets() -> bool {
+  LOG_PACKETS.load(Ordering::Relaxed)
+}
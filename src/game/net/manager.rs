@@ -1,16 +1,24 @@
 use std::fmt;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 
+use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
 use tokio::task::JoinHandle;
+use tokio::time;
 
-use tokio_rustls::TlsAcceptor;
-
+use super::packet::{DisconnectPacket, DisconnectReason};
+use super::{Acceptor, HeartbeatConfig, Heartbeats};
+use crate::game::accounts::AccountStoreHandle;
 use crate::game::GameServerHandle;
 
 // Structures
 
+/// Time a closing connection gets to close TLS and WebSocket sessions
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
 #[derive(Clone, Debug)]
 pub struct NetManagerHandle {
     pub address: SocketAddr,
@@ -20,13 +28,25 @@ pub struct NetManagerHandle {
 pub struct NetManagerActor {
     pub address: SocketAddr,
     pub stream: Option<TcpStream>,
-    tls_acceptor: Option<TlsAcceptor>,
+    acceptor: Acceptor,
     server: GameServerHandle,
+    accounts: AccountStoreHandle,
+    limits: Arc<super::PacketLimits>,
+    heartbeat: HeartbeatConfig,
+    /// Whether the handshake is completed and the connection entered the lobby
+    established: bool,
+    heartbeats: Heartbeats,
+    /// Notified as soon as the connection is closed after a disconnect
+    disconnected: Option<oneshot::Sender<()>>,
 }
 
 #[derive(Debug)]
 enum NetManagerMessage {
     StopActor,
+    LeaveGame,
+    Disconnect(DisconnectReason, String, oneshot::Sender<()>),
+    Established,
+    HeartbeatEcho(u32),
 }
 
 // Implementations
@@ -36,7 +56,9 @@ impl fmt::Debug for NetManagerActor {
         f.debug_struct("NetManagerActor")
             .field("address", &self.address)
             .field("stream", &self.stream)
-            .field("tls_acceptor", &self.tls_acceptor.as_ref().map(|_| "<...>"))
+            .field("acceptor", &self.acceptor)
+            .field("established", &self.established)
+            .field("heartbeats", &self.heartbeats)
             .finish()
     }
 }
@@ -48,122 +70,247 @@ impl NetManagerHandle {
             .await
             .expect("NetManagerActor was dropped, oopsie!")
     }
+    /// Tells the client why it is disconnected, closes the connection
+    /// and waits until it is closed
+    pub async fn disconnect(&mut self, reason: DisconnectReason, message: String) {
+        let (send, recv) = oneshot::channel();
+        let msg = NetManagerMessage::Disconnect(reason, message, send);
+        // The connection might be closed already
+        if self.sender.send(msg).await.is_ok() {
+            drop(recv.await);
+        }
+    }
+    /// Moves the connection from its game back into the lobby
+    pub async fn leave_game(&mut self) {
+        // The connection might be closing already
+        drop(self.sender.send(NetManagerMessage::LeaveGame).await);
+    }
+    /// Ends the handshake timeout and starts sending heartbeats
+    pub async fn established(&mut self) {
+        drop(self.sender.send(NetManagerMessage::Established).await);
+    }
+    /// Reports that the client echoed a heartbeat
+    pub async fn heartbeat_echo(&mut self, id: u32) {
+        drop(self.sender.send(NetManagerMessage::HeartbeatEcho(id)).await);
+    }
 }
 
 impl NetManagerActor {
     pub fn new(
         address: SocketAddr,
         stream: TcpStream,
-        tls_acceptor: TlsAcceptor,
+        acceptor: Acceptor,
         gs_handle: GameServerHandle,
+        accounts: AccountStoreHandle,
+        limits: Arc<super::PacketLimits>,
+        heartbeat: HeartbeatConfig,
     ) -> Self {
         Self {
             address,
             stream: Some(stream),
-            tls_acceptor: Some(tls_acceptor),
+            acceptor,
             server: gs_handle,
+            accounts,
+            limits,
+            heartbeat,
+            established: false,
+            heartbeats: Heartbeats::default(),
+            disconnected: None,
         }
     }
     pub fn spawn(self) -> (NetManagerHandle, JoinHandle<NetManagerActor>) {
         let (send, recv) = mpsc::channel(1024);
+        let handle = NetManagerHandle {
+            address: self.address,
+            sender: send,
+        };
 
         (
-            NetManagerHandle {
-                address: self.address,
-                sender: send,
-            },
-            tokio::spawn(async move { self.actor(recv).await }),
+            handle.clone(),
+            tokio::spawn(async move { self.actor(recv, handle).await }),
         )
     }
-    async fn actor(mut self, mut recv: mpsc::Receiver<NetManagerMessage>) -> Self {
+    async fn actor(
+        mut self,
+        mut recv: mpsc::Receiver<NetManagerMessage>,
+        handle: NetManagerHandle,
+    ) -> Self {
         use futures::future::FutureExt;
-        let stream = std::mem::replace(&mut self.stream, None).unwrap();
+        let stream = self.stream.take().unwrap();
+        // The sender flushes as soon as it has no more packets to write,
+        // waiting for more data like Nagle's algorithm only adds latency
+        drop(stream.set_nodelay(true));
+
+        // The TLS and WebSocket handshakes count towards the handshake timeout as well
+        let handshake_timeout = time::delay_for(self.heartbeat.handshake_timeout);
 
-        // Establish TLS
-        let acceptor = std::mem::replace(&mut self.tls_acceptor, None).unwrap();
-        let stream = match acceptor.accept(stream).await {
-            Err(e) => {
+        // Establish TLS and WebSocket sessions, if the listener uses them
+        let accept = self.acceptor.accept(stream, self.limits.largest());
+        let stream = match time::timeout_at(handshake_timeout.deadline(), accept)
+            .await
+        {
+            Err(_) => {
+                eprintln!("(⚠) Accepting the connection of {} timed out", self.address);
+                return self;
+            }
+            Ok(Err(e)) => {
                 eprintln!(
-                    "(⚠) TLS handshake with {addr} failed: {err}",
+                    "(⚠) Accepting the connection of {addr} failed: {err}",
                     addr = self.address,
                     err = e
                 );
                 return self;
             }
-            Ok(s) => s,
+            Ok(Ok(s)) => s,
         };
 
         // Split into actors
         let (rh, wh) = tokio::io::split(stream);
 
         // Spawn Send Actor
-        let send_actor = super::sender::NetSenderActor::new(wh, self.address.clone());
+        let send_actor = super::sender::NetSenderActor::new(wh, self.address, self.limits.send_queue);
         let (mut send_handle, send_jh) = send_actor.spawn();
 
         // Spawn Receive Actor
         let recv_actor = super::receiver::NetReceiverActor::new(
             rh,
             send_handle.clone(),
-            self.address.clone(),
+            self.address,
             self.server.clone(),
+            self.accounts.clone(),
+            self.limits.clone(),
+            handle,
         );
         let (mut recv_handle, recv_jh) = recv_actor.spawn();
 
+        let period = self.heartbeat.interval;
+        let mut heartbeat_interval = time::interval_at(time::Instant::now() + period, period);
+
         tokio::pin! {
             let recv_finished = recv_jh;
             let send_finished = send_jh;
+            let handshake_timeout = handshake_timeout;
         }
 
-        let mut recv_actor = None;
-        let mut send_actor = None;
+        let recv_actor;
+        let send_actor;
 
         loop {
-            tokio::select! {
+            let keep_running = tokio::select! {
                 msg = recv.recv().fuse() => {
-                    if let Some(msg) = msg {
-                        if !self.process_msg(msg).await {
-                            recv_handle.stop_actor().await;
-                            send_handle.stop_actor().await;
-                            break;
+                    match msg {
+                        Some(msg) => {
+                            self.process_msg(msg, &mut recv_handle, &mut send_handle)
+                                .await
                         }
-                    } else {
-                        recv_handle.stop_actor().await;
-                        send_handle.stop_actor().await;
-                        let res = tokio::join!(recv_finished, send_finished);
-                        recv_actor = Some(res.0.unwrap());
-                        send_actor = Some(res.1.unwrap());
-                        break;
+                        None => false,
                     }
                 }
+                _ = &mut handshake_timeout, if !self.established => {
+                    let message = "Handshake timed out".to_string();
+                    self.disconnect(&mut send_handle, DisconnectReason::IdleTimeout, message)
+                        .await;
+                    false
+                }
+                _ = heartbeat_interval.tick(), if self.established => {
+                    self.heartbeat(&mut send_handle).await
+                }
                 act = &mut recv_finished => {
-                    send_handle.stop_actor().await;
-                    recv_actor = Some(act.unwrap());
-                    send_actor = Some(send_finished.await.unwrap());
+                    send_handle.stop_actor();
+                    recv_actor = act.unwrap();
+                    send_actor = send_finished.await.unwrap();
                     break;
                 }
                 act = &mut send_finished => {
                     recv_handle.stop_actor().await;
-                    recv_actor = Some(recv_finished.await.unwrap());
-                    send_actor = Some(act.unwrap());
+                    recv_actor = recv_finished.await.unwrap();
+                    send_actor = act.unwrap();
                     break;
                 }
+            };
+            if !keep_running {
+                recv_handle.stop_actor().await;
+                send_handle.stop_actor();
+                let res = tokio::join!(recv_finished, send_finished);
+                recv_actor = res.0.unwrap();
+                send_actor = res.1.unwrap();
+                break;
             }
         }
 
+        if let Some(e) = &send_actor.error {
+            eprintln!("(⚠) Failed to send packets to {}: {}", self.address, e);
+        }
+
         // Shutdown connection
-        let (rh, wh): (tokio::io::ReadHalf<_>, _) =
-            (recv_actor.unwrap().into(), send_actor.unwrap().into());
-        let (stream, _session) = rh.unsplit(wh).into_inner();
-        drop(stream.shutdown(std::net::Shutdown::Both));
-        self.stream = Some(stream);
+        let (rh, wh): (tokio::io::ReadHalf<_>, _) = (recv_actor.into(), send_actor.into());
+        let mut stream = rh.unsplit(wh);
+        // Tell TLS and WebSocket clients that the connection is closed
+        drop(time::timeout(CLOSE_TIMEOUT, stream.shutdown()).await);
+        drop(stream.tcp().shutdown(std::net::Shutdown::Both));
+        if let Some(cb) = self.disconnected.take() {
+            let _ = cb.send(());
+        }
 
         self
     }
 
-    async fn process_msg(&mut self, msg: NetManagerMessage) -> bool {
+    async fn process_msg(
+        &mut self,
+        msg: NetManagerMessage,
+        recv_handle: &mut super::NetReceiverHandle,
+        send_handle: &mut super::NetSenderHandle,
+    ) -> bool {
         match msg {
-            NetManagerMessage::StopActor => return false,
-            //_ => true,
+            NetManagerMessage::StopActor => false,
+            NetManagerMessage::LeaveGame => {
+                recv_handle.leave_game().await;
+                true
+            }
+            NetManagerMessage::Disconnect(reason, message, cb) => {
+                self.disconnect(send_handle, reason, message).await;
+                self.disconnected = Some(cb);
+                false
+            }
+            NetManagerMessage::Established => {
+                self.established = true;
+                true
+            }
+            NetManagerMessage::HeartbeatEcho(id) => {
+                self.heartbeats.echo(id);
+                true
+            }
+        }
+    }
+
+    /// Sends the next heartbeat, unless too many are unanswered already
+    async fn heartbeat(&mut self, send_handle: &mut super::NetSenderHandle) -> bool {
+        if self.heartbeats.missed() >= self.heartbeat.max_missed {
+            let message = format!(
+                "Missed {} heartbeats (round-trip time {:?})",
+                self.heartbeats.missed(),
+                self.heartbeats.rtt()
+            );
+            self.disconnect(send_handle, DisconnectReason::IdleTimeout, message)
+                .await;
+            return false;
         }
+        send_handle.send_packet(self.heartbeats.send());
+        true
+    }
+
+    /// Tells the client why the connection is going to be closed
+    async fn disconnect(
+        &mut self,
+        send_handle: &mut super::NetSenderHandle,
+        reason: DisconnectReason,
+        message: String,
+    ) {
+        println!(
+            "(ℹ) Disconnecting {} ({:?}): {}",
+            self.address, reason, message
+        );
+        // The sender writes this packet before it stops
+        send_handle.send_packet(DisconnectPacket { reason, message });
     }
 }
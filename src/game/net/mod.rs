@@ -1,9 +1,23 @@
+mod heartbeat;
+mod limits;
+mod listener;
+mod log;
 mod manager;
 mod receiver;
 mod sender;
+mod tls;
+mod transport;
+mod websocket;
 
+pub use heartbeat::*;
+pub use limits::*;
+pub use listener::*;
+pub use log::set_log_packets;
 pub use manager::*;
 pub use receiver::*;
 pub use sender::*;
+pub use tls::*;
+pub use transport::*;
+pub use websocket::*;
 
-pub mod packet;
\ No newline at end of file
+pub use haendler_protocol::packet;
\ No newline at end of file
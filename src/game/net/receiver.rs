@@ -1,15 +1,17 @@
+use std::convert::TryInto;
 use std::net::SocketAddr;
 use std::sync::Arc;
 
-use tokio::io::BufReader;
-use tokio::net::tcp::OwnedReadHalf;
+use haendler_protocol::frame::{Header, HEADER_LEN};
+use tokio::io::{AsyncRead, AsyncReadExt};
 use tokio::sync::mpsc;
 use tokio::task::JoinHandle;
 
-use openssl::rsa::Rsa;
-use openssl::symm::{Cipher, Crypter, Mode};
-
-use super::NetSenderHandle;
+use super::packet::{Capabilities, DecodeError, DisconnectReason};
+use super::{NetManagerHandle, NetSenderHandle, PacketLimits};
+use crate::game::accounts::AccountStoreHandle;
+use crate::game::permission_level::PermissionLevel;
+use crate::game::{GameHandle, GameServerHandle};
 
 // Structures
 
@@ -18,52 +20,63 @@ pub struct NetReceiverHandle {
   sender: mpsc::Sender<NetReceiverMessage>,
 }
 
-type ReadHalf = BufReader<OwnedReadHalf>;
+type ReadHalf = tokio::io::ReadHalf<super::NetStream>;
 const NET_BUFFER_SIZE: usize = 2 * 1024;
 
 #[derive(Debug)]
 pub struct NetReceiverActor {
-  pub read_half: ReadHalf,
+  pub read_half: FrameReader<ReadHalf>,
   pub sender: NetSenderHandle,
   pub address: SocketAddr,
-  encryption_status: EncryptionStatus,
-  verify_key: Option<Arc<Vec<u8>>>,
+  server: GameServerHandle,
+  accounts: AccountStoreHandle,
+  limits: Arc<PacketLimits>,
+  manager: NetManagerHandle,
   state: State,
+  permission_level: PermissionLevel,
+  username: Option<String>,
+  /// Capabilities negotiated during the handshake
+  capabilities: Capabilities,
+  /// Game of this connection while in the `Game` state
+  game: Option<GameHandle>,
+  /// State the server left before the client knew about it. Packets
+  /// of this state are discarded until the client sends a packet of
+  /// the current state.
+  left_state: Option<State>,
 }
 
 #[derive(Debug)]
 enum NetReceiverMessage {
   StopActor,
+  LeaveGame,
 }
 
-enum EncryptionStatus {
-  Unencrypted(Arc<(Rsa<openssl::pkey::Private>, Vec<u8>)>),
-  Encrypted(Crypter),
+/// Reads frames from a stream. Reading may be cancelled at any time, the
+/// bytes of an incomplete frame stay buffered until the rest arrives.
+#[derive(Debug)]
+pub struct FrameReader<R> {
+  reader: R,
+  buffer: Vec<u8>,
 }
 
-impl std::fmt::Debug for EncryptionStatus {
-  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    let encrypted = if let Self::Unencrypted(_) = self {
-      false
-    } else {
-      true
-    };
-    f.debug_struct("EncryptionStatus")
-      .field("encrypted", &encrypted)
-      .finish()
-  }
+#[derive(Debug)]
+enum ReadError {
+  /// The connection was closed or failed
+  Closed,
+  /// The body of the packet exceeds this limit
+  Oversized(Header, u32),
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub enum State {
-  Handshake,
-  Ping,
-  Encrypt,
-  Login,
-}
+pub use haendler_protocol::State;
 
 // Implementations
 
+impl From<NetReceiverActor> for ReadHalf {
+  fn from(actor: NetReceiverActor) -> Self {
+    actor.read_half.reader
+  }
+}
+
 impl NetReceiverHandle {
   pub async fn stop_actor(&mut self) {
     self
@@ -72,22 +85,37 @@ impl NetReceiverHandle {
       .await
       .expect("NetReceiverActor was dropped, oopsie!")
   }
+  /// Moves the connection from its game back into the lobby
+  pub async fn leave_game(&mut self) {
+    // The actor stops as soon as the connection closes
+    drop(self.sender.send(NetReceiverMessage::LeaveGame).await);
+  }
 }
 
 impl NetReceiverActor {
   pub fn new(
-    read_half: OwnedReadHalf,
-    encryption: Arc<(Rsa<openssl::pkey::Private>, Vec<u8>)>,
+    read_half: ReadHalf,
     sender: NetSenderHandle,
     address: SocketAddr,
+    server: GameServerHandle,
+    accounts: AccountStoreHandle,
+    limits: Arc<PacketLimits>,
+    manager: NetManagerHandle,
   ) -> Self {
     Self {
-      read_half: BufReader::with_capacity(NET_BUFFER_SIZE, read_half),
-      encryption_status: EncryptionStatus::Unencrypted(encryption),
-      verify_key: None,
+      read_half: FrameReader::new(read_half),
       state: State::Handshake,
+      permission_level: PermissionLevel::Guest,
+      username: None,
+      capabilities: Capabilities::default(),
+      game: None,
+      left_state: None,
       sender,
       address,
+      server,
+      accounts,
+      limits,
+      manager,
     }
   }
   pub fn spawn(self) -> (NetReceiverHandle, JoinHandle<NetReceiverActor>) {
@@ -101,7 +129,8 @@ impl NetReceiverActor {
   async fn actor(mut self, mut recv: mpsc::Receiver<NetReceiverMessage>) -> Self {
     loop {
       tokio::select! {
-        // Used only for termination
+        // Messages of the manager, e.g. to stop or to leave the game.
+        // Reading packets is cancelled without losing data.
         msg = recv.recv() => {
           if let Some(msg) = msg {
             if !self.process_msg(msg).await {
@@ -114,15 +143,20 @@ impl NetReceiverActor {
         result = self.read_packet() => {
           match result {
             Ok((packet_id, packet_body)) => {
-              // DEBUG: dump packet
-              println!("Received packet ({:#X})", packet_id);
-              for byte in &packet_body {
-                print!("{:02x}", byte);
+              if super::log::log_packets() {
+                println!("Received packet ({:#X})", packet_id);
+                for byte in &packet_body {
+                  print!("{:02x}", byte);
+                }
+                println!();
               }
-              println!();
 
               match self.process_packet(packet_id, &packet_body).await {
-                Err(_) => break,
+                Err(e) => {
+                  let e = e.in_packet(packet_id, self.state, packet_body.len());
+                  self.decode_error(e).await;
+                  break;
+                }
                 Ok(true) => (),
                 Ok(false) => break,
               }
@@ -137,67 +171,41 @@ impl NetReceiverActor {
 
   async fn process_msg(&mut self, msg: NetReceiverMessage) -> bool {
     match msg {
-      NetReceiverMessage::StopActor => return false,
-      //_ => true,
+      NetReceiverMessage::StopActor => false,
+      NetReceiverMessage::LeaveGame => {
+        // The server already removed this connection from its game
+        if self.state == State::Game {
+          self.state = State::Login;
+          self.left_state = Some(State::Game);
+          self.game = None;
+          self.server.enter_lobby(self.sender.clone()).await;
+        }
+        true
+      }
     }
   }
 
   async fn read_packet(&mut self) -> Result<(u16, Vec<u8>), ()> {
-    use byteorder::{LittleEndian, ReadBytesExt};
-    use std::convert::TryInto;
-    use tokio::io::AsyncReadExt;
-    // The packet header is always 6 bytes long
-    let mut header = [0; 6];
-
-    // Read header
-    match self.read_half.read_exact(&mut header).await {
-      Ok(bytes) => {
-        debug_assert_eq!(bytes, header.len());
-
-        // Decrypt
-        if let EncryptionStatus::Encrypted(crypter) = &mut self.encryption_status {
-          header = decrypt_header(crypter, header);
-        }
-
-        // Parse header (packet id [2 bytes] + body length [4 bytes])
-        let mut header_slice = &header[..];
-        let packet_id = ReadBytesExt::read_u16::<LittleEndian>(&mut header_slice).unwrap();
-        let body_len = ReadBytesExt::read_u32::<LittleEndian>(&mut header_slice)
-          .unwrap()
-          .try_into()
-          .unwrap();
-        let mut body = vec![0; body_len];
-
-        // Read body
-        match self.read_half.read_exact(&mut body).await {
-          Ok(bytes) => {
-            if bytes != body_len {
-              #[cfg(debug_assertions)]
-              {
-                eprintln!(
-                  "Unexpected body length (expected {}, found {})",
-                  body_len, bytes
-                )
-              }
-              return Err(());
-            }
-
-            // Decrypt
-            if let EncryptionStatus::Encrypted(crypter) = &mut self.encryption_status {
-              body = decrypt_vec(crypter, body);
-            }
-
-            Ok((packet_id, body))
-          }
-          Err(_) => Err(()),
-        }
+    match self.read_half.read(&self.limits, self.state).await {
+      Ok(packet) => Ok(packet),
+      Err(ReadError::Closed) => Err(()),
+      Err(ReadError::Oversized(header, max_body_len)) => {
+        let packet_id = header.packet_id;
+        eprintln!(
+          "(⚠) {} sent an oversized packet {:#X} ({:?}, {} > {} bytes)",
+          self.address, packet_id, self.state, header.body_len, max_body_len
+        );
+        let message = format!("Packet {:#X} exceeds {} bytes", packet_id, max_body_len);
+        self
+          .disconnect(DisconnectReason::ProtocolError, message)
+          .await;
+        Err(())
       }
-      Err(_) => return Err(()),
     }
   }
 
-  async fn process_packet(&mut self, id: u16, mut data: &[u8]) -> Result<bool, ()> {
-    use super::packet::{self, serial::SerialRead, IngoingPacket};
+  async fn process_packet(&mut self, id: u16, mut data: &[u8]) -> Result<bool, DecodeError> {
+    use super::packet::{self, serial::SerialRead, DecodeErrorKind, IngoingPacket};
 
     macro_rules! switch {
       {
@@ -209,17 +217,43 @@ impl NetReceiverActor {
       } => {
         match ($id, $state) {
           $(
-            ($crate::game::net::packet::$P::ID, $crate::game::net::packet::$P::STATE) => {
+            (id, state) if id == packet::$P::ID
+              && (state == packet::$P::STATE || packet::$P::STATE == State::Any) => {
+              if self.permission_level < packet::$P::PERMISSION {
+                // Reject the packet without running its handler
+                eprintln!(
+                  "(⚠) {} is not permitted to send packet {:#X} ({:?})",
+                  self.address, $id, $state
+                );
+                let response = packet::PermissionDeniedPacket {
+                  packet_id: $id,
+                  required: packet::$P::PERMISSION,
+                };
+                self.sender.send_packet(response);
+                return Ok(true);
+              }
               let $pv = packet::$P::read(&mut $data)?;
-              if $data.len() != 0 {
+              if !$data.is_empty() {
                 // The packet contained too many bytes
-                eprintln!("(⚠) Inflated packet from {}", self.address);
-                return Err(());
+                return Err(DecodeError::new(DecodeErrorKind::TrailingBytes, $data.len()));
+              }
+              if packet::$P::STATE != State::Any {
+                // The client knows the current state
+                self.left_state = None;
               }
               $code
             }
           )*
-          _ => Err(())
+          (id, _) => {
+            let left = self.left_state.map_or(false, |left| {
+              false $(|| (id == packet::$P::ID && packet::$P::STATE == left))*
+            });
+            if left {
+              // Sent before the client learned about the state change
+              return Ok(true);
+            }
+            Err(DecodeError::new(DecodeErrorKind::UnknownPacket, $data.len()))
+          }
         }
       };
     }
@@ -229,38 +263,36 @@ impl NetReceiverActor {
 
       // --- State = Handshake ---
       packet = HandshakePacket => {
-        println!("Action: {:?}", packet.action);
-        use super::packet::HandshakeAction;
+        use super::packet::{HandshakeAction, HandshakeResponsePacket, PROTOCOL_VERSION};
+        let supported = packet.protocol_version == PROTOCOL_VERSION;
+        if !supported {
+          if let HandshakeAction::Connect = packet.action {
+            let message = format!(
+              "Unsupported protocol version {}, this server requires version {}",
+              packet.protocol_version, PROTOCOL_VERSION
+            );
+            self.disconnect(DisconnectReason::UnsupportedVersion, message).await;
+            return Ok(false);
+          }
+          // Pinging is still allowed, so clients can show why they can't connect
+        }
+        self.capabilities = packet.capabilities.intersection(Capabilities::SUPPORTED);
+        let response = HandshakeResponsePacket {
+          protocol_version: PROTOCOL_VERSION,
+          capabilities: self.capabilities,
+        };
+        self.sender.send_packet(response);
         match packet.action {
           HandshakeAction::Ping => {
-            use super::packet::PingStatusPacket;
             self.state = State::Ping;
-            let response = PingStatusPacket {
-              players: 0,
-              games: 0,
-              status: r#"{"text":"Test server"}"#.into()
-            };
-            self.sender.send_packet(response).await;
+            let response = self.server.get_status().await;
+            self.sender.send_packet(response);
             Ok(true)
           }
           HandshakeAction::Connect => {
-            use super::packet::RequestEncryptionPacket;
-            self.state = State::Encrypt;
-            let mut verify = [0; 64];
-            openssl::rand::rand_bytes(&mut verify).unwrap();
-            let verify: Arc<Vec<u8>> = Arc::new((&verify[..]).into());
-            self.verify_key = Some(verify.clone());
-
-            let pkey = match &self.encryption_status {
-              EncryptionStatus::Encrypted(_) => unreachable!(),
-              EncryptionStatus::Unencrypted(pk) => pk.clone()
-            };
-
-            let response = RequestEncryptionPacket {
-              public_key: pkey,
-              verify
-            };
-            self.sender.send_packet(response).await;
+            self.state = State::Login;
+            self.manager.established().await;
+            self.server.enter_lobby(self.sender.clone()).await;
             Ok(true)
           }
         }
@@ -268,65 +300,317 @@ impl NetReceiverActor {
 
       // --- State = Ping ---
       packet = PingPongPacket => {
-        self.sender.send_packet(packet).await;
+        self.sender.send_packet(packet);
         Ok(false)
       }
 
-      // --- State = Encrypt ---
-      packet = EncryptionResponsePacket => {
-        use super::packet::EncryptionSuccessPacket;
-        println!("Got EncryptionResponsePacket");
-        if self.verify_key.is_none() {
-          return Err(());
-        }
+      // --- State = Any ---
+      packet = HeartbeatPacket => {
+        self.manager.heartbeat_echo(packet.id).await;
+        Ok(true)
+      }
 
-        let verify = self.verify_key.as_ref().unwrap();
-        let pkey = match &self.encryption_status {
-          EncryptionStatus::Encrypted(_) => unreachable!(),
-          EncryptionStatus::Unencrypted(pk) => pk
+      // --- State = Login ---
+      _packet = SyncGamesPacket => {
+        self.server.sync_games(self.address).await;
+        Ok(true)
+      }
+      packet = LoginPacket => {
+        use super::packet::LoginResponsePacket;
+        let level = if packet.username.is_empty() && packet.password.is_empty() {
+          // Log off
+          None
+        } else {
+          let level = self
+            .accounts
+            .login(packet.username.clone(), packet.password)
+            .await;
+          match level {
+            Some(level) => println!(
+              "(ℹ) {} logged in as {:?} ({:?})",
+              self.address, packet.username, level
+            ),
+            None => println!(
+              "(⚠) {} failed to log in as {:?}",
+              self.address, packet.username
+            ),
+          }
+          level
+        };
+        self.permission_level = level.unwrap_or(PermissionLevel::Guest);
+        let username = packet.username;
+        self.username = level.map(|_| username);
+        let response = LoginResponsePacket {
+          permission_level: self.permission_level,
+        };
+        self.sender.send_packet(response);
+        Ok(true)
+      }
+      packet = CreateGamePacket => {
+        use super::packet::{CreateGameResponsePacket, GameActionStatus};
+        let response = match valid_game_name(packet.name) {
+          Some(name) => CreateGameResponsePacket {
+            status: GameActionStatus::Success,
+            id: self.server.create_game(name).await,
+          },
+          None => CreateGameResponsePacket {
+            status: GameActionStatus::InvalidName,
+            id: 0,
+          },
+        };
+        self.sender.send_packet(response);
+        Ok(true)
+      }
+      packet = DeleteGamePacket => {
+        use super::packet::{DeleteGameResponsePacket, GameActionStatus};
+        let status = if self.server.delete_game(packet.id).await {
+          GameActionStatus::Success
+        } else {
+          GameActionStatus::NotFound
         };
+        let response = DeleteGameResponsePacket {
+          status,
+          id: packet.id,
+        };
+        self.sender.send_packet(response);
+        Ok(true)
+      }
+      packet = RenameGamePacket => {
+        use super::packet::{GameActionStatus, RenameGameResponsePacket};
+        let status = match valid_game_name(packet.name) {
+          Some(name) => {
+            if self.server.rename_game(packet.id, name).await {
+              GameActionStatus::Success
+            } else {
+              GameActionStatus::NotFound
+            }
+          }
+          None => GameActionStatus::InvalidName,
+        };
+        let response = RenameGameResponsePacket {
+          status,
+          id: packet.id,
+        };
+        self.sender.send_packet(response);
+        Ok(true)
+      }
+      packet = JoinGamePacket => {
+        // The game itself confirms a successful join
+        self.game = self.server.join_game(packet.id, self.sender.clone()).await;
+        if self.game.is_some() {
+          self.state = State::Game;
+          // The client only knows once it receives the response
+          self.left_state = Some(State::Login);
+        } else {
+          use super::packet::{GameActionStatus, JoinGameResponsePacket};
+          let response = JoinGameResponsePacket {
+            status: GameActionStatus::NotFound,
+            id: packet.id,
+          };
+          self.sender.send_packet(response);
+        }
+        Ok(true)
+      }
+
+      // --- State = Game ---
+      _packet = LeaveGamePacket => {
+        self.state = State::Login;
+        self.game = None;
+        self.server.leave_game(self.sender.clone()).await;
+        Ok(true)
+      }
+      packet = RequestGameInfoPacket => {
+        self.route_to_game(packet::GamePacket::RequestGameInfo(packet)).await;
+        Ok(true)
+      }
+      packet = ChatPacket => {
+        self.route_to_game(packet::GamePacket::Chat(packet)).await;
+        Ok(true)
+      }
+    }
+  }
 
-        // If the encryption cannot be verified, the
-        // connection is terminated
-        let secret = packet.verify(pkey, verify)?;
+  /// Reports a malformed packet before the connection is closed
+  async fn decode_error(&mut self, error: DecodeError) {
+    eprintln!("(⚠) Malformed packet from {}: {}", self.address, error);
+    // Only debug builds tell clients what they did wrong, if they want to know
+    if cfg!(debug_assertions) && self.capabilities.contains(Capabilities::DECODE_ERRORS) {
+      use super::packet::DecodeErrorPacket;
+      let packet_id = error.packet.map_or(0, |(id, _)| id);
+      let response = DecodeErrorPacket {
+        packet_id,
+        kind: error.kind,
+        offset: error.offset as u32,
+        field: error.field.unwrap_or_default().into(),
+      };
+      self.sender.send_packet(response);
+    }
+    self
+      .disconnect(DisconnectReason::ProtocolError, error.to_string())
+      .await;
+  }
 
-        // Encrypt the sender
-        self.sender.encrypt(secret.clone()).await;
+  /// Tells the client why the connection is going to be closed
+  async fn disconnect(&mut self, reason: DisconnectReason, message: String) {
+    use super::packet::DisconnectPacket;
+    println!(
+      "(ℹ) Disconnecting {} ({:?}): {}",
+      self.address, reason, message
+    );
+    self
+      .sender
+      .send_packet(DisconnectPacket { reason, message });
+  }
 
-        print!("SECRET: ");
-        for byte in &secret {
-          print!("{:02X}", byte);
-        }
-        println!();
+  /// Forwards an in-game packet to the game of this connection
+  async fn route_to_game(&mut self, packet: super::packet::GamePacket) {
+    use crate::game::Player;
+    let player = Player {
+      sender: self.sender.clone(),
+      username: self.username.clone(),
+      permission_level: self.permission_level,
+    };
+    if let Some(game) = self.game.as_mut() {
+      game.send_packet(player, packet).await;
+    }
+  }
+}
 
-        // Encrypt the reader
-        {
-          let cipher = Cipher::aes_128_cfb8();
-          let crypter = Crypter::new(cipher, Mode::Decrypt, &secret, Some(&secret)).unwrap();
-          self.encryption_status = EncryptionStatus::Encrypted(crypter);
+impl<R: AsyncRead + Unpin> FrameReader<R> {
+  pub fn new(reader: R) -> Self {
+    Self {
+      reader,
+      buffer: Vec::with_capacity(NET_BUFFER_SIZE),
+    }
+  }
+  /// Reads the ID and body of the next packet. Its length is checked
+  /// against the limits of the state before the body is received.
+  async fn read(
+    &mut self,
+    limits: &PacketLimits,
+    state: State,
+  ) -> Result<(u16, Vec<u8>), ReadError> {
+    loop {
+      if self.buffer.len() >= HEADER_LEN {
+        let header = Header::parse(self.buffer[..HEADER_LEN].try_into().unwrap());
+        let body_len = limits
+          .body_len(state, header)
+          .map_err(|max_body_len| ReadError::Oversized(header, max_body_len))?;
+        let frame_len = HEADER_LEN + body_len;
+        if self.buffer.len() >= frame_len {
+          let body = self.buffer[HEADER_LEN..frame_len].to_vec();
+          self.buffer.drain(..frame_len);
+          // Don't keep a huge buffer after a large packet
+          if self.buffer.is_empty() && self.buffer.capacity() > 4 * NET_BUFFER_SIZE {
+            self.buffer = Vec::with_capacity(NET_BUFFER_SIZE);
+          }
+          return Ok((header.packet_id, body));
         }
+      }
+      // A single read either completes or reads nothing, so it is safe
+      // to cancel, unlike `read_exact`
+      let mut chunk = [0; NET_BUFFER_SIZE];
+      let read = self
+        .reader
+        .read(&mut chunk)
+        .await
+        .map_err(|_| ReadError::Closed)?;
+      if read == 0 {
+        return Err(ReadError::Closed);
+      }
+      self.buffer.extend_from_slice(&chunk[..read]);
+    }
+  }
+}
 
-        let response = EncryptionSuccessPacket {};
-        self.sender.send_packet(response).await;
+/// Trims a game name and returns it if it is not empty
+fn valid_game_name(name: String) -> Option<String> {
+  let name = name.trim();
+  if name.is_empty() {
+    None
+  } else {
+    Some(name.into())
+  }
+}
 
-        self.state = State::Login;
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::Duration;
+
+  use haendler_protocol::frame;
+  use haendler_protocol::packet::ChatPacket;
+  use tokio::io::AsyncWriteExt;
+  use tokio::net::{TcpListener, TcpStream};
+  use tokio::time;
+
+  async fn connection() -> (TcpStream, FrameReader<TcpStream>) {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap());
+    let (client, accepted) = tokio::join!(client, listener.accept());
+    (client.unwrap(), FrameReader::new(accepted.unwrap().0))
+  }
 
-        Ok(true)
+  fn chat(message: &str) -> Vec<u8> {
+    frame::encode(
+      2,
+      ChatPacket {
+        message: message.into(),
+      },
+    )
+  }
+
+  #[tokio::test]
+  async fn leave_game_during_partial_frame() {
+    let (mut client, mut reader) = connection().await;
+    let limits = PacketLimits::new(1024);
+    let first = chat("hello");
+    let second = chat("world");
+
+    // Half of the header and half of the body
+    for split in &[3, HEADER_LEN + 4] {
+      client.write_all(&first[..*split]).await.unwrap();
+      // The actor stops reading like this when a LeaveGame message arrives
+      let read = reader.read(&limits, State::Game);
+      let leave = time::delay_for(Duration::from_millis(50));
+      tokio::select! {
+        _ = read => panic!("read an incomplete frame"),
+        _ = leave => (),
       }
+      client.write_all(&first[*split..]).await.unwrap();
+      client.write_all(&second).await.unwrap();
+
+      let (id, body) = reader.read(&limits, State::Game).await.unwrap();
+      assert_eq!((id, &body[..]), (2, &first[HEADER_LEN..]));
+      let (id, body) = reader.read(&limits, State::Game).await.unwrap();
+      assert_eq!((id, &body[..]), (2, &second[HEADER_LEN..]));
     }
   }
-}
 
-fn decrypt_header(crypter: &mut Crypter, data: [u8; 6]) -> [u8; 6] {
-  let mut buf = [0; 6];
-  let len = crypter.update(&data, &mut buf).unwrap();
-  debug_assert_eq!(len, 6);
-  buf
-}
+  #[tokio::test]
+  async fn rejects_oversized_frame() {
+    let (mut client, mut reader) = connection().await;
+    let limits = PacketLimits::new(8);
+    // Only the header is sent, the body is never allocated
+    client
+      .write_all(&chat("too long")[..HEADER_LEN])
+      .await
+      .unwrap();
+    match reader.read(&limits, State::Game).await {
+      Err(ReadError::Oversized(header, 8)) => assert_eq!(header.body_len, 12),
+      result => panic!("unexpected result {:?}", result),
+    }
+  }
 
-fn decrypt_vec(crypter: &mut Crypter, data: Vec<u8>) -> Vec<u8> {
-  let mut buf = vec![0; data.len()];
-  let len = crypter.update(&data, &mut buf).unwrap();
-  debug_assert_eq!(len, buf.len());
-  buf
+  #[tokio::test]
+  async fn reports_closed_connection() {
+    let (mut client, mut reader) = connection().await;
+    let limits = PacketLimits::new(1024);
+    client.write_all(&chat("hello")[..4]).await.unwrap();
+    drop(client);
+    match reader.read(&limits, State::Game).await {
+      Err(ReadError::Closed) => (),
+      result => panic!("unexpected result {:?}", result),
+    }
+  }
 }
@@ -1,41 +1,91 @@
+use std::collections::VecDeque;
+use std::io;
+use std::mem;
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use std::{cmp, hash::Hasher};
 
-use tokio::io::BufWriter;
-use tokio::sync::mpsc;
+use tokio::sync::Notify;
 use tokio::task::JoinHandle;
+use tokio::time;
 
-use super::packet::serial::SerialWrite;
-use super::packet::OutgoingPacket;
+use super::packet::{
+  DisconnectPacket, DisconnectReason, ListGamesEntry, ListGamesPacket, OutgoingPacket,
+};
+use haendler_protocol::frame;
 
 // Structures
 
+/// Queues packets for a connection. Sending never waits for the peer,
+/// connections that can't keep up are disconnected instead.
 #[derive(Clone, Debug)]
 pub struct NetSenderHandle {
-  sender: mpsc::Sender<NetSenderMessage>,
+  queue: Arc<SendQueue>,
   address: SocketAddr,
 }
 
-type WriteHalf = tokio::io::WriteHalf<tokio_rustls::server::TlsStream<tokio::net::TcpStream>>;
-type Writer = BufWriter<WriteHalf>;
-const NET_BUFFER_SIZE: usize = 2 * 1024;
+type WriteHalf = tokio::io::WriteHalf<super::NetStream>;
+/// Packets are collected up to this size before they are written
+const NET_BUFFER_SIZE: usize = 64 * 1024;
+/// Time a closing connection gets to receive its last packets
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
 
 pub struct NetSenderActor {
-  pub write_half: Writer,
+  pub write_half: WriteHalf,
   pub address: SocketAddr,
+  queue: Arc<SendQueue>,
+  /// Packets that are not written yet
+  buffer: Vec<u8>,
+  /// Error that stopped this actor
+  pub error: Option<io::Error>,
 }
 
+/// Packets shared between the handles and the actor
 #[derive(Debug)]
-enum NetSenderMessage {
-  StopActor,
-  SendPacket(Vec<u8>),
+struct SendQueue {
+  state: Mutex<QueueState>,
+  /// Notified whenever the state changes
+  notify: Notify,
+  /// Maximum number of bytes queued or being written
+  limit: usize,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+  packets: VecDeque<Queued>,
+  /// Size of the queued packets and the packets being written
+  bytes: usize,
+  stopped: bool,
+  /// Set once the limit was exceeded, nothing is queued afterwards
+  overflowed: bool,
+}
+
+#[derive(Debug)]
+enum Queued {
+  Frame(Vec<u8>),
+  Shared(EncodedPacket),
+  /// Merged with later changes until they are written
+  GameChanges(GameChanges),
+}
+
+/// Packet including its header. It is encoded once and shared by all
+/// connections it is sent to.
+#[derive(Clone, Debug)]
+pub struct EncodedPacket(Arc<[u8]>);
+
+/// Changes of the game list, sent to every connection in the lobby
+#[derive(Clone, Debug)]
+pub struct GameChanges {
+  entries: Arc<Vec<ListGamesEntry>>,
+  packet: EncodedPacket,
 }
 
 // Implementations
 
 impl From<NetSenderActor> for WriteHalf {
   fn from(actor: NetSenderActor) -> Self {
-    actor.write_half.into_inner()
+    actor.write_half
   }
 }
 
@@ -45,80 +95,386 @@ impl cmp::PartialEq for NetSenderHandle {
   }
 }
 
+impl cmp::Eq for NetSenderHandle {}
+
 impl std::hash::Hash for NetSenderHandle {
   fn hash<H: Hasher>(&self, state: &mut H) {
     self.address.hash(state);
   }
 }
 
-const ACTOR_DROPPED_ERROR: &'static str = "NetSenderActor was dropped, oopsie!";
-
 impl NetSenderHandle {
-  pub async fn stop_actor(&mut self) {
-    self
-      .sender
-      .send(NetSenderMessage::StopActor)
-      .await
-      .expect(ACTOR_DROPPED_ERROR)
-  }
-  pub async fn send_packet<P: OutgoingPacket>(&mut self, packet: P) {
-    let mut buf = Vec::with_capacity(40);
-    SerialWrite::write_consume(P::ID, &mut buf);
-
-    let mut pbuf = Vec::with_capacity(32);
-    SerialWrite::write_consume(packet, &mut pbuf);
-
-    SerialWrite::write_consume(
-      std::convert::TryInto::<u32>::try_into(pbuf.len()).expect("Packet is too large!"),
-      &mut buf,
-    );
+  pub fn address(&self) -> SocketAddr {
+    self.address
+  }
+  /// Writes the queued packets and stops the actor
+  pub fn stop_actor(&self) {
+    self.queue.state.lock().unwrap().stopped = true;
+    self.queue.notify.notify();
+  }
+  pub fn send_packet<P: OutgoingPacket>(&self, packet: P) {
+    self.queue.push(Queued::Frame(frame::encode(P::ID, packet)));
+  }
+  pub fn send_encoded(&self, packet: &EncodedPacket) {
+    self.queue.push(Queued::Shared(packet.clone()));
+  }
+  /// Sends changes of the game list. Changes that are still queued
+  /// are merged into a single packet.
+  pub fn send_game_changes(&self, changes: &GameChanges) {
+    self.queue.push(Queued::GameChanges(changes.clone()));
+  }
+}
+
+/// Encodes a packet once and sends it to every connection
+pub fn broadcast<'a, P, I>(senders: I, packet: P)
+where
+  P: OutgoingPacket,
+  I: IntoIterator<Item = &'a NetSenderHandle>,
+{
+  let packet = EncodedPacket::new(packet);
+  for sender in senders {
+    sender.send_encoded(&packet);
+  }
+}
+
+impl EncodedPacket {
+  pub fn new<P: OutgoingPacket>(packet: P) -> Self {
+    Self(frame::encode(P::ID, packet).into())
+  }
+  fn len(&self) -> usize {
+    self.0.len()
+  }
+}
 
-    buf.append(&mut pbuf);
+impl GameChanges {
+  pub fn new(entries: Vec<ListGamesEntry>) -> Self {
+    Self {
+      packet: EncodedPacket::new(ListGamesPacket {
+        entries: entries.clone(),
+      }),
+      entries: Arc::new(entries),
+    }
+  }
+  /// Applies later changes, which requires encoding the packet again
+  fn merge(&mut self, later: &GameChanges) {
+    let entries = Arc::make_mut(&mut self.entries);
+    // Only the latest change of a game matters
+    entries.retain(|entry| later.entries.iter().all(|change| change.id() != entry.id()));
+    entries.extend(later.entries.iter().cloned());
+    self.packet = EncodedPacket::new(ListGamesPacket {
+      entries: entries.clone(),
+    });
+  }
+}
 
-    self
-      .sender
-      .send(NetSenderMessage::SendPacket(buf))
-      .await
-      .expect(ACTOR_DROPPED_ERROR)
+impl SendQueue {
+  /// Queues a packet unless the limit is exceeded. The connection may
+  /// close at any time, packets to a closed connection are discarded.
+  fn push(&self, packet: Queued) {
+    let mut guard = self.state.lock().unwrap();
+    let state = &mut *guard;
+    if state.stopped || state.overflowed {
+      return;
+    }
+    let mut bytes = state.bytes + packet.size();
+    let packet = match (state.packets.back_mut(), packet) {
+      (Some(Queued::GameChanges(queued)), Queued::GameChanges(changes)) => {
+        let old_size = queued.packet.len();
+        queued.merge(&changes);
+        bytes = state.bytes - old_size + queued.packet.len();
+        None
+      }
+      (_, packet) => Some(packet),
+    };
+    if bytes > self.limit {
+      state.overflowed = true;
+    } else {
+      state.bytes = bytes;
+      state.packets.extend(packet);
+    }
+    drop(guard);
+    self.notify.notify();
+  }
+  /// Waits for packets. Returns them and whether the actor should stop
+  /// afterwards, or `None` if the limit was exceeded.
+  async fn take(&self) -> Option<(Vec<Queued>, bool)> {
+    loop {
+      {
+        let mut state = self.state.lock().unwrap();
+        if state.overflowed {
+          return None;
+        }
+        if !state.packets.is_empty() || state.stopped {
+          let packets = mem::take(&mut state.packets).into();
+          return Some((packets, state.stopped));
+        }
+      }
+      self.notify.notified().await;
+    }
+  }
+  /// Completes once the limit was exceeded
+  async fn overflowed(&self) {
+    while !self.state.lock().unwrap().overflowed {
+      self.notify.notified().await;
+    }
+  }
+  /// Marks written packets as sent
+  fn written(&self, bytes: usize) {
+    self.state.lock().unwrap().bytes -= bytes;
+  }
+}
+
+impl Queued {
+  fn size(&self) -> usize {
+    self.data().len()
+  }
+  fn data(&self) -> &[u8] {
+    match self {
+      Self::Frame(data) => data,
+      Self::Shared(packet) => &packet.0,
+      Self::GameChanges(changes) => &changes.packet.0,
+    }
   }
 }
 
 impl NetSenderActor {
-  pub fn new(write_half: WriteHalf, address: SocketAddr) -> Self {
+  /// `limit` is the maximum number of bytes queued for the connection
+  pub fn new(write_half: WriteHalf, address: SocketAddr, limit: usize) -> Self {
     Self {
-      write_half: BufWriter::with_capacity(NET_BUFFER_SIZE, write_half),
+      write_half,
       address,
+      queue: Arc::new(SendQueue {
+        state: Mutex::default(),
+        notify: Notify::new(),
+        limit,
+      }),
+      buffer: Vec::with_capacity(NET_BUFFER_SIZE),
+      error: None,
     }
   }
   pub fn spawn(self) -> (NetSenderHandle, JoinHandle<NetSenderActor>) {
-    let (send, recv) = mpsc::channel(1024);
-
     (
       NetSenderHandle {
-        sender: send,
-        address: self.address.clone(),
+        queue: self.queue.clone(),
+        address: self.address,
       },
-      tokio::spawn(async move { self.actor(recv).await }),
+      tokio::spawn(async move { self.actor().await }),
     )
   }
-  async fn actor(mut self, mut recv: mpsc::Receiver<NetSenderMessage>) -> Self {
-    use tokio::io::AsyncWriteExt;
-    use tokio::io::Result;
+  async fn actor(mut self) -> Self {
+    let queue = self.queue.clone();
     loop {
-      match recv.recv().await {
-        None => return self,
-        Some(NetSenderMessage::StopActor) => return self,
-        Some(NetSenderMessage::SendPacket(data)) => {
-          if let Result::Err(_err) = self.write_half.write(&data).await {
-            return self;
-          }
-          println!("Sending packet");
-          for byte in &data {
-            print!("{:02X}", byte);
-          }
-          println!();
+      // Wait for the next packets
+      let (packets, stopped) = match queue.take().await {
+        Some(taken) => taken,
+        None => {
+          self.slow_connection().await;
+          return self;
+        }
+      };
+      for packet in packets {
+        self.queue_packet(packet.data());
+      }
+      let bytes = self.buffer.len();
+
+      let result = if stopped {
+        // Deliver the last packets as well, e.g. a disconnect
+        time::timeout(CLOSE_TIMEOUT, self.flush()).await.ok()
+      } else {
+        // Stop writing as soon as the peer falls too far behind
+        tokio::select! {
+          result = self.flush() => Some(result),
+          _ = queue.overflowed() => None,
         }
+      };
+      match result {
+        Some(Ok(())) => queue.written(bytes),
+        Some(Err(e)) => {
+          self.error = Some(e);
+          return self;
+        }
+        None if !stopped => {
+          self.slow_connection().await;
+          return self;
+        }
+        None => return self,
       }
+      if stopped {
+        return self;
+      }
+    }
+  }
+
+  /// Adds a packet to the buffer
+  fn queue_packet(&mut self, data: &[u8]) {
+    if super::log::log_packets() {
+      println!("Sending packet");
+      for byte in data {
+        print!("{:02X}", byte);
+      }
+      println!();
+    }
+    self.buffer.extend_from_slice(data);
+  }
+
+  /// Writes all buffered packets to the connection. Packets that are not
+  /// written yet stay in the buffer if writing is cancelled.
+  async fn flush(&mut self) -> io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+    if self.buffer.is_empty() {
+      return Ok(());
+    }
+    while !self.buffer.is_empty() {
+      let written = self.write_half.write(&self.buffer).await?;
+      if written == 0 {
+        return Err(io::ErrorKind::WriteZero.into());
+      }
+      self.buffer.drain(..written);
+    }
+    self.write_half.flush().await?;
+    // Don't keep a huge buffer after a burst of packets
+    if self.buffer.capacity() > 4 * NET_BUFFER_SIZE {
+      self.buffer = Vec::with_capacity(NET_BUFFER_SIZE);
+    }
+    Ok(())
+  }
+
+  /// Disconnects a client that doesn't receive its packets fast enough
+  async fn slow_connection(&mut self) {
+    let message = format!("More than {} bytes of packets are queued", self.queue.limit);
+    println!(
+      "(ℹ) Disconnecting {} ({:?}): {}",
+      self.address,
+      DisconnectReason::SlowConnection,
+      message
+    );
+    // The queued packets are discarded, but a packet may be partially
+    // written already. It is completed before the client gets to know why.
+    let packet = DisconnectPacket {
+      reason: DisconnectReason::SlowConnection,
+      message,
+    };
+    self.queue_packet(&frame::encode(DisconnectPacket::ID, packet));
+    drop(time::timeout(CLOSE_TIMEOUT, self.flush()).await);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use haendler_protocol::frame::HEADER_LEN;
+  use haendler_protocol::packet::serial::SerialRead;
+
+  fn queue(limit: usize) -> SendQueue {
+    SendQueue {
+      state: Mutex::default(),
+      notify: Notify::new(),
+      limit,
+    }
+  }
+
+  fn add(id: u64, name: &str) -> ListGamesEntry {
+    ListGamesEntry::Add {
+      id,
+      name: name.into(),
+      players: 0,
+    }
+  }
+
+  /// Decodes the entries of an encoded List Games packet
+  fn decode(packet: &EncodedPacket) -> Vec<ListGamesEntry> {
+    let mut data = &packet.0[HEADER_LEN..];
+    let packet = ListGamesPacket::read(&mut data).unwrap();
+    assert!(data.is_empty());
+    packet.entries
+  }
+
+  fn ids(entries: &[ListGamesEntry]) -> Vec<(u64, bool)> {
+    let removed = |entry: &ListGamesEntry| matches!(entry, ListGamesEntry::Remove { .. });
+    entries.iter().map(|e| (e.id(), removed(e))).collect()
+  }
+
+  #[test]
+  fn merge_keeps_latest_change_per_game() {
+    let mut changes = GameChanges::new(vec![add(1, "a"), add(2, "b")]);
+    changes.merge(&GameChanges::new(vec![
+      ListGamesEntry::Remove { id: 1 },
+      add(3, "c"),
+    ]));
+    changes.merge(&GameChanges::new(vec![add(2, "renamed")]));
+
+    let expected = [(1, true), (3, false), (2, false)];
+    assert_eq!(ids(&changes.entries), expected);
+    // The packet is encoded again
+    let entries = decode(&changes.packet);
+    assert_eq!(ids(&entries), expected);
+    match &entries[2] {
+      ListGamesEntry::Add { name, .. } => assert_eq!(name, "renamed"),
+      entry => panic!("unexpected entry {:?}", entry),
     }
   }
+
+  #[test]
+  fn merge_doesnt_affect_other_connections() {
+    let shared = GameChanges::new(vec![add(1, "a")]);
+    let mut merged = shared.clone();
+    merged.merge(&GameChanges::new(vec![add(2, "b")]));
+    assert_eq!(ids(&shared.entries), [(1, false)]);
+    assert_eq!(ids(&decode(&shared.packet)), [(1, false)]);
+  }
+
+  #[test]
+  fn queued_changes_are_merged() {
+    let queue = queue(usize::MAX);
+    let frame = frame::encode(DisconnectPacket::ID, 0u8);
+    let frame_len = frame.len();
+    queue.push(Queued::Frame(frame));
+    queue.push(Queued::GameChanges(GameChanges::new(vec![add(1, "a")])));
+    queue.push(Queued::GameChanges(GameChanges::new(vec![add(1, "b")])));
+    queue.push(Queued::GameChanges(GameChanges::new(vec![add(2, "c")])));
+
+    let mut state = queue.state.lock().unwrap();
+    assert_eq!(state.packets.len(), 2);
+    let merged = match state.packets.back() {
+      Some(Queued::GameChanges(changes)) => changes.clone(),
+      packet => panic!("unexpected packet {:?}", packet),
+    };
+    assert_eq!(ids(&merged.entries), [(1, false), (2, false)]);
+    assert_eq!(state.bytes, frame_len + merged.packet.len());
+
+    // Changes after other packets are queued separately
+    let taken: Vec<Queued> = mem::take(&mut state.packets).into();
+    drop(state);
+    queue.push(Queued::GameChanges(GameChanges::new(vec![add(3, "d")])));
+    assert_eq!(queue.state.lock().unwrap().packets.len(), 1);
+    let size = queue.state.lock().unwrap().packets[0].size();
+
+    let written = taken.iter().map(Queued::size).sum();
+    queue.written(written);
+    assert_eq!(queue.state.lock().unwrap().bytes, size);
+  }
+
+  #[test]
+  fn overflow_stops_queueing() {
+    let frame = frame::encode(DisconnectPacket::ID, 0u32);
+    let queue = queue(2 * frame.len());
+    queue.push(Queued::Frame(frame.clone()));
+    queue.push(Queued::Frame(frame.clone()));
+    assert!(!queue.state.lock().unwrap().overflowed);
+
+    queue.push(Queued::Frame(frame.clone()));
+    {
+      let state = queue.state.lock().unwrap();
+      assert!(state.overflowed);
+      assert_eq!(state.packets.len(), 2);
+      assert_eq!(state.bytes, 2 * frame.len());
+    }
+
+    // Nothing is queued afterwards, even if it fits again
+    queue.written(2 * frame.len());
+    queue.push(Queued::Frame(vec![0]));
+    let state = queue.state.lock().unwrap();
+    assert_eq!(state.packets.len(), 2);
+    assert_eq!(state.bytes, 0);
+  }
 }
@@ -0,0 +1,195 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+use futures::StreamExt;
+use tokio::time;
+use tokio_rustls::rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use tokio_rustls::rustls::sign;
+use tokio_rustls::rustls::{Certificate, NoClientAuth, PrivateKey, ServerConfig, SignatureScheme};
+use tokio_rustls::TlsAcceptor;
+
+// Structures
+
+/// Certificate chain and private key of the server. They can be reloaded
+/// without a restart, which only affects new connections.
+pub struct TlsCertificate {
+  cert: PathBuf,
+  key: PathBuf,
+  acceptor: RwLock<TlsAcceptor>,
+  /// Modification times of the files when they were read last
+  loaded: Mutex<Modified>,
+}
+
+type Modified = (Option<SystemTime>, Option<SystemTime>);
+
+/// Interval in which the files are checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(30);
+
+// Implementations
+
+impl TlsCertificate {
+  /// Loads a PEM encoded certificate chain, starting with the certificate
+  /// of the server, and its PKCS#8 or RSA private key
+  pub fn load(cert: PathBuf, key: PathBuf) -> io::Result<Self> {
+    let loaded = modified(&cert, &key);
+    let acceptor = read(&cert, &key)?;
+    Ok(Self {
+      cert,
+      key,
+      acceptor: RwLock::new(acceptor),
+      loaded: Mutex::new(loaded),
+    })
+  }
+  /// Acceptor using the latest certificate
+  pub fn acceptor(&self) -> TlsAcceptor {
+    self.acceptor.read().unwrap().clone()
+  }
+  /// Reads the files again. The previous certificate stays in use if
+  /// they are invalid.
+  pub fn reload(&self) {
+    *self.loaded.lock().unwrap() = modified(&self.cert, &self.key);
+    match read(&self.cert, &self.key) {
+      Ok(acceptor) => {
+        *self.acceptor.write().unwrap() = acceptor;
+        println!("(ℹ) Reloaded TLS certificate {}", self.cert.display());
+      }
+      Err(e) => eprintln!(
+        "(⚠) Reloading the TLS certificate failed, the previous one stays in use: {}",
+        e
+      ),
+    }
+  }
+  /// Reloads the files on SIGHUP or once they were changed. Never completes.
+  pub async fn watch(self: Arc<Self>) {
+    let polls = time::interval(WATCH_INTERVAL).map(|_| false);
+    #[cfg(unix)]
+    let mut triggers = {
+      use tokio::signal::unix::{signal, SignalKind};
+      match signal(SignalKind::hangup()) {
+        Ok(hangups) => futures::stream::select(polls, hangups.map(|_| true)).boxed(),
+        Err(e) => {
+          eprintln!("(⚠) Failed to install SIGHUP handler: {}", e);
+          polls.boxed()
+        }
+      }
+    };
+    #[cfg(not(unix))]
+    let mut triggers = polls;
+
+    // Changed files are only read once they stayed the same for an
+    // interval, so a certificate is never paired with an outdated key
+    let mut seen = modified(&self.cert, &self.key);
+    while let Some(hangup) = triggers.next().await {
+      let current = modified(&self.cert, &self.key);
+      let changed = current == seen && current != *self.loaded.lock().unwrap();
+      if hangup || changed {
+        self.reload();
+      }
+      seen = current;
+    }
+  }
+}
+
+impl fmt::Debug for TlsCertificate {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("TlsCertificate")
+      .field("cert", &self.cert)
+      .field("key", &self.key)
+      .finish()
+  }
+}
+
+fn modified(cert: &Path, key: &Path) -> Modified {
+  let modified = |path| fs::metadata(path).and_then(|m| m.modified()).ok();
+  (modified(cert), modified(key))
+}
+
+/// Reads the certificate chain and key and creates an acceptor for them
+fn read(cert: &Path, key: &Path) -> io::Result<TlsAcceptor> {
+  let chain = read_pem(cert, certs)?;
+  if chain.is_empty() {
+    return Err(invalid(format!(
+      "{} contains no certificate",
+      cert.display()
+    )));
+  }
+  let mut keys = read_pem(key, pkcs8_private_keys)?;
+  if keys.is_empty() {
+    keys = read_pem(key, rsa_private_keys)?;
+  }
+  let key_der = match keys.len() {
+    1 => keys.remove(0),
+    0 => {
+      return Err(invalid(format!(
+        "{} contains no PKCS#8 or RSA private key (convert other keys with \
+         `openssl pkcs8 -topk8 -nocrypt`)",
+        key.display()
+      )))
+    }
+    n => {
+      return Err(invalid(format!(
+        "{} contains {} private keys instead of one",
+        key.display(),
+        n
+      )))
+    }
+  };
+  check_key(&chain[0], &key_der).map_err(|e| {
+    invalid(format!(
+      "{} doesn't fit the certificate {}: {}",
+      key.display(),
+      cert.display(),
+      e
+    ))
+  })?;
+  let mut config = ServerConfig::new(NoClientAuth::new());
+  config
+    .set_single_cert(chain, key_der)
+    .map_err(|e| invalid(format!("{}: {}", key.display(), e)))?;
+  Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Checks that the key belongs to the certificate by verifying a signature
+fn check_key(certificate: &Certificate, key: &PrivateKey) -> Result<(), String> {
+  let key = sign::any_supported_type(key).map_err(|_| "unsupported private key type")?;
+  let schemes = [
+    SignatureScheme::RSA_PSS_SHA256,
+    SignatureScheme::ECDSA_NISTP256_SHA256,
+    SignatureScheme::ECDSA_NISTP384_SHA384,
+    SignatureScheme::ED25519,
+  ];
+  let signer = key
+    .choose_scheme(&schemes)
+    .ok_or("unsupported private key type")?;
+  let algorithm = match signer.get_scheme() {
+    SignatureScheme::RSA_PSS_SHA256 => &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    SignatureScheme::ECDSA_NISTP256_SHA256 => &webpki::ECDSA_P256_SHA256,
+    SignatureScheme::ECDSA_NISTP384_SHA384 => &webpki::ECDSA_P384_SHA384,
+    _ => &webpki::ED25519,
+  };
+  let message = b"haendlerspiel";
+  let signature = signer.sign(message).map_err(|e| e.to_string())?;
+  let certificate = webpki::EndEntityCert::from(&certificate.0)
+    .map_err(|e| format!("invalid certificate ({:?})", e))?;
+  certificate
+    .verify_signature(algorithm, message, &signature)
+    .map_err(|_| "the public key of the certificate is different".into())
+}
+
+fn read_pem<T>(
+  path: &Path,
+  parse: fn(&mut dyn BufRead) -> Result<Vec<T>, ()>,
+) -> io::Result<Vec<T>> {
+  let file = File::open(path)
+    .map_err(|e| io::Error::new(e.kind(), format!("can't open {}: {}", path.display(), e)))?;
+  parse(&mut BufReader::new(file))
+    .map_err(|_| invalid(format!("{} is not a valid PEM file", path.display())))
+}
+
+fn invalid(message: String) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,127 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::server::TlsStream;
+
+use super::{TlsCertificate, WebSocketConnection};
+
+// Structures
+
+/// How connections are secured before packets are exchanged
+#[derive(Clone)]
+pub enum Transport {
+  Tls(Arc<TlsCertificate>),
+  /// Packets are exchanged directly over TCP. Only for development!
+  Plaintext,
+}
+
+/// How the connections of a listener are accepted
+#[derive(Clone, Debug)]
+pub struct Acceptor {
+  pub transport: Transport,
+  /// Whether packets are sent as WebSocket messages, e.g. by browsers
+  pub websocket: bool,
+}
+
+/// Connection to a client
+#[derive(Debug)]
+pub enum NetStream {
+  Tls(Box<TlsStream<TcpStream>>),
+  Plaintext(TcpStream),
+  WebSocket(Box<WebSocketConnection>),
+}
+
+// Implementations
+
+impl Transport {
+  pub fn tls(cert: PathBuf, key: PathBuf) -> io::Result<Self> {
+    Ok(Self::Tls(Arc::new(TlsCertificate::load(cert, key)?)))
+  }
+  /// Performs the TLS handshake, if there is one
+  pub async fn accept(&self, stream: TcpStream) -> io::Result<NetStream> {
+    match self {
+      Self::Tls(certificate) => {
+        let stream = certificate.acceptor().accept(stream).await?;
+        Ok(NetStream::Tls(Box::new(stream)))
+      }
+      Self::Plaintext => Ok(NetStream::Plaintext(stream)),
+    }
+  }
+}
+
+impl Acceptor {
+  /// Performs the TLS and WebSocket handshakes. WebSocket messages may
+  /// contain packets with bodies of up to `max_body_size` bytes.
+  pub async fn accept(&self, stream: TcpStream, max_body_size: u32) -> io::Result<NetStream> {
+    let stream = self.transport.accept(stream).await?;
+    if self.websocket {
+      let connection = WebSocketConnection::accept(stream, max_body_size).await?;
+      Ok(NetStream::WebSocket(Box::new(connection)))
+    } else {
+      Ok(stream)
+    }
+  }
+}
+
+impl fmt::Debug for Transport {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Tls(certificate) => f.debug_tuple("Tls").field(certificate).finish(),
+      Self::Plaintext => f.write_str("Plaintext"),
+    }
+  }
+}
+
+impl NetStream {
+  pub fn tcp(&self) -> &TcpStream {
+    match self {
+      Self::Tls(stream) => stream.get_ref().0,
+      Self::Plaintext(stream) => stream,
+      Self::WebSocket(connection) => connection.get_ref().tcp(),
+    }
+  }
+}
+
+impl AsyncRead for NetStream {
+  fn poll_read(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut [u8],
+  ) -> Poll<io::Result<usize>> {
+    match self.get_mut() {
+      Self::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+      Self::Plaintext(stream) => Pin::new(stream).poll_read(cx, buf),
+      Self::WebSocket(connection) => Pin::new(connection).poll_read(cx, buf),
+    }
+  }
+}
+
+impl AsyncWrite for NetStream {
+  fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+    match self.get_mut() {
+      Self::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+      Self::Plaintext(stream) => Pin::new(stream).poll_write(cx, buf),
+      Self::WebSocket(connection) => Pin::new(connection).poll_write(cx, buf),
+    }
+  }
+  fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    match self.get_mut() {
+      Self::Tls(stream) => Pin::new(stream).poll_flush(cx),
+      Self::Plaintext(stream) => Pin::new(stream).poll_flush(cx),
+      Self::WebSocket(connection) => Pin::new(connection).poll_flush(cx),
+    }
+  }
+  fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    match self.get_mut() {
+      Self::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+      Self::Plaintext(stream) => Pin::new(stream).poll_shutdown(cx),
+      Self::WebSocket(connection) => Pin::new(connection).poll_shutdown(cx),
+    }
+  }
+}
@@ -0,0 +1,147 @@
+use std::convert::TryInto;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::{ready, Sink, Stream};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+use tokio_tungstenite::WebSocketStream;
+
+use super::NetStream;
+use haendler_protocol::frame::{Header, HEADER_LEN};
+
+// Structures
+
+/// WebSocket connection of a browser. Every binary message carries exactly
+/// one packet, so the receiver and sender can treat it like any other stream.
+#[derive(Debug)]
+pub struct WebSocketConnection {
+  stream: WebSocketStream<NetStream>,
+  /// Received packet that is not read completely yet
+  incoming: Vec<u8>,
+  read: usize,
+  /// Written packets that are not sent as messages yet
+  outgoing: Vec<u8>,
+}
+
+// Implementations
+
+impl WebSocketConnection {
+  /// Performs the WebSocket handshake. Messages may contain packets with
+  /// bodies of up to `max_body_size` bytes.
+  pub async fn accept(stream: NetStream, max_body_size: u32) -> io::Result<Self> {
+    let max_message_size = HEADER_LEN + max_body_size as usize;
+    let config = WebSocketConfig {
+      max_message_size: Some(max_message_size),
+      max_frame_size: Some(max_message_size),
+      ..WebSocketConfig::default()
+    };
+    let stream = tokio_tungstenite::accept_async_with_config(stream, Some(config))
+      .await
+      .map_err(ws_error)?;
+    Ok(Self {
+      stream,
+      incoming: Vec::new(),
+      read: 0,
+      outgoing: Vec::new(),
+    })
+  }
+  pub fn get_ref(&self) -> &NetStream {
+    self.stream.get_ref()
+  }
+  /// Sends all complete packets that were written
+  fn poll_send(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    while let Some(len) = frame_len(&self.outgoing) {
+      ready!(Pin::new(&mut self.stream).poll_ready(cx)).map_err(ws_error)?;
+      let rest = self.outgoing.split_off(len);
+      let packet = std::mem::replace(&mut self.outgoing, rest);
+      Pin::new(&mut self.stream)
+        .start_send(Message::Binary(packet))
+        .map_err(ws_error)?;
+    }
+    Poll::Ready(Ok(()))
+  }
+}
+
+/// Length of the first packet, if it is complete
+fn frame_len(data: &[u8]) -> Option<usize> {
+  let header = Header::parse(data.get(..HEADER_LEN)?.try_into().unwrap());
+  let len = HEADER_LEN + header.body_len as usize;
+  if data.len() >= len {
+    Some(len)
+  } else {
+    None
+  }
+}
+
+fn ws_error(e: WsError) -> io::Error {
+  match e {
+    WsError::Io(e) => e,
+    e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+  }
+}
+
+impl AsyncRead for WebSocketConnection {
+  fn poll_read(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut [u8],
+  ) -> Poll<io::Result<usize>> {
+    let this = self.get_mut();
+    loop {
+      if this.read < this.incoming.len() {
+        let len = buf.len().min(this.incoming.len() - this.read);
+        buf[..len].copy_from_slice(&this.incoming[this.read..this.read + len]);
+        this.read += len;
+        return Poll::Ready(Ok(len));
+      }
+      match ready!(Pin::new(&mut this.stream).poll_next(cx)) {
+        Some(Ok(Message::Binary(data))) => {
+          if frame_len(&data) != Some(data.len()) {
+            return Poll::Ready(Err(io::Error::new(
+              io::ErrorKind::InvalidData,
+              "every WebSocket message must contain exactly one packet",
+            )));
+          }
+          this.incoming = data;
+          this.read = 0;
+        }
+        Some(Ok(Message::Text(_))) => {
+          return Poll::Ready(Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "packets must be sent as binary WebSocket messages",
+          )))
+        }
+        // Pings are answered by tungstenite
+        Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => continue,
+        Some(Ok(Message::Close(_))) | None => return Poll::Ready(Ok(0)),
+        Some(Err(e)) => return Poll::Ready(Err(ws_error(e))),
+      }
+    }
+  }
+}
+
+impl AsyncWrite for WebSocketConnection {
+  fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+    let this = self.get_mut();
+    // Nothing is accepted until the previous packets are sent
+    ready!(this.poll_send(cx))?;
+    this.outgoing.extend_from_slice(buf);
+    if let Poll::Ready(Err(e)) = this.poll_send(cx) {
+      return Poll::Ready(Err(e));
+    }
+    Poll::Ready(Ok(buf.len()))
+  }
+  fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    let this = self.get_mut();
+    ready!(this.poll_send(cx))?;
+    Pin::new(&mut this.stream).poll_flush(cx).map_err(ws_error)
+  }
+  fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    let this = self.get_mut();
+    ready!(this.poll_send(cx))?;
+    Pin::new(&mut this.stream).poll_close(cx).map_err(ws_error)
+  }
+}
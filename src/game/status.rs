@@ -0,0 +1,22 @@
+use serde::Serialize;
+
+/// Status shown in the server list of the launcher, sent as JSON
+/// in every [`PingStatusPacket`](super::net::packet::PingStatusPacket)
+#[derive(Clone, Debug, Serialize)]
+pub struct ServerStatus {
+  /// Message of the day
+  pub text: String,
+  pub version: String,
+  /// Protocol version clients need to connect
+  pub protocol: u16,
+  pub max_players: u32,
+  /// PNG image as a data URL
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub icon: Option<String>,
+}
+
+impl ServerStatus {
+  pub fn to_json(&self) -> String {
+    serde_json::to_string(self).expect("ServerStatus is always valid JSON")
+  }
+}
@@ -5,14 +5,33 @@ pub mod game;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
-    let options = game::config::Options::from_args();
+    let config = match game::config::Options::from_args().load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("(⚠) {}", e);
+            std::process::exit(2);
+        }
+    };
+    game::net::set_log_packets(config.log_packets);
 
-    let game_server = game::GameServerActor::new(([127, 0, 0, 1], 25252), options.load()?);
+    let accounts = game::accounts::AccountStoreActor::load(&config.accounts)?;
+    let (mut accounts_handle, accounts_join_handle) = accounts.spawn();
+
+    let game_server = game::GameServerActor::new(
+        config.listeners()?,
+        config.transport.clone(),
+        accounts_handle.clone(),
+        config.status()?,
+        config.limits.clone(),
+        config.heartbeat,
+    );
     let (mut handle, join_handle) = game_server.spawn();
 
     wait_ctrl_c_signal().await;
     handle.stop_actor().await;
     join_handle.await?;
+    accounts_handle.stop_actor().await;
+    accounts_join_handle.await?;
 
     println!("Goodbye!");
     Ok(())